@@ -1,28 +1,137 @@
-#[derive(Debug, Clone)]
+use crate::span::Span;
+use serde::Serialize;
+use std::fmt;
+
+#[derive(Debug, Clone, Serialize)]
 pub enum Literal {
     Str(String),
     Num(f64),
     Bool(bool),
-    Array(Vec<Literal>),
+    Null,
+}
+
+// the type in a parameter annotation such as `<b is optional string Array>`,
+// or the right-hand side of an `is` test
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Type {
+    // string, number, bool, null, record, function or any; in `is` tests also a predicate
+    Named(String),
+    // `<type> Array`: an array whose every element has that type
+    Array(Box<Type>),
+    // `optional <type>`: the type or null
+    Optional(Box<Type>),
+    // `<type> or <type> ...`
+    Union(Vec<Type>),
+}
+
+pub const TYPE_NAMES: &[&str] = &["string", "number", "bool", "null", "record", "function", "any"];
+
+// the predicates every interpreter knows; the host program may register more
+pub const PREDICATE_NAMES: &[&str] = &["realNumber", "integer", "empty"];
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Array(elem) if **elem == Type::Named("any".into()) => write!(f, "Array"),
+            Type::Array(elem) => write!(f, "{} Array", elem),
+            Type::Optional(inner) => write!(f, "optional {}", inner),
+            Type::Union(options) => {
+                for (i, t) in options.iter().enumerate() {
+                    if i > 0 { write!(f, " or ")?; }
+                    write!(f, "{}", t)?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
-pub enum Expr {
-    Import { name: String, alias: Option<String>, module: String },
-    Function { params: Vec<String>, types: Vec<(String,String)>, body: Vec<Stmt> },
+// what an import brings into scope
+#[derive(Debug, Clone, Serialize)]
+pub enum Imported {
+    // `import a, b -> c from mod`: names from the module, each with an optional alias
+    Names(Vec<(String, Option<String>)>),
+    // `import * from mod`: every public name of the module
+    All,
+    // `import mod` / `import mod -> m`: the module itself, as a record of its public names
+    Module(Option<String>),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum ExprKind {
+    Import { what: Imported, module: String },
+    Function { name: Option<String>, params: Vec<String>, types: Vec<(String, Type)>, body: Vec<Stmt> },
     Call { callee: Box<Expr>, args: Vec<Expr> },
     If { cond: Box<Expr>, then_body: Vec<Stmt>, else_body: Option<Vec<Stmt>> },
     Run(Box<Expr>),
     Return(Box<Expr>),
+    Assign { name: String, value: Box<Expr> },
     Binary { left: Box<Expr>, op: String, right: Box<Expr> },
+    Unary { op: String, expr: Box<Expr> },
+    // `x is number`, `x is string Array`, `x is integer`: a type or a named predicate
+    Is { expr: Box<Expr>, ty: Type },
     Var(String),
     Lit(Literal),
+    // [a, b, c]
+    Array(Vec<Expr>),
+    // `list at i` / `list[i]`
+    Index { target: Box<Expr>, index: Box<Expr> },
+    // `list at i = value` / `list[i] = value`
+    SetIndex { target: Box<Expr>, index: Box<Expr>, value: Box<Expr> },
+    // { name: "x", age: 3 }
+    Record(Vec<(String, Expr)>),
+    // `user.name` / `name of user`
+    Field { target: Box<Expr>, name: String },
+    // `user.name = value` / `name of user = value`
+    SetField { target: Box<Expr>, name: String, value: Box<Expr> },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
 }
 
-#[derive(Debug, Clone)]
-pub enum Stmt {
+#[derive(Debug, Clone, Serialize)]
+pub enum StmtKind {
     Expr(Expr),
     FunctionDef(Expr),
-    ImportStmt(Expr),
-    Empty,
-}
\ No newline at end of file
+    // `let x = ...` / `const x = ...` always bind in the current scope
+    Let { name: String, value: Expr, constant: bool },
+    Import(Expr),
+    // `export let ...`, `export const ...` or `export __fn ...`, at the top level of a file
+    Export(Box<Stmt>),
+    While { cond: Expr, body: Vec<Stmt> },
+    // each <var> in <iter> - do, ... __
+    Each { var: String, iter: Expr, body: Vec<Stmt> },
+    Repeat { count: Expr, body: Vec<Stmt> },
+    Stop,
+    Skip,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Expr { kind, span }
+    }
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Stmt { kind, span }
+    }
+
+    // the definition behind an `export`, or the statement itself
+    pub fn unexported(&self) -> &Stmt {
+        match &self.kind {
+            StmtKind::Export(inner) => inner,
+            _ => self,
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use crate::environment::{Environment, Value};
+use crate::interpreter::{describe, Interp, RuntimeError, E_ARITY, E_TYPE};
+use std::cmp::Ordering;
+
+// everything programs can use without importing it
+pub fn install(env: &Environment) {
+    install_core(env);
+    install_collections(env);
+}
+
+fn install_core(env: &Environment) {
+    // add(1, 2, 3): the sum of any number of numbers
+    env.define_builtin("add", None, |_, args| {
+        let mut sum = 0.0;
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                Value::Num(n) => sum += n,
+                other => return Err(argument_error("add", i + 1, "a number", other)),
+            }
+        }
+        Ok(Value::Num(sum))
+    });
+
+    // print(a, b, ...): the values on one line, separated by spaces
+    env.define_builtin("print", None, |_, args| {
+        let line: Vec<String> = args.iter().map(Value::to_string).collect();
+        println!("{}", line.join(" "));
+        Ok(Value::Null)
+    });
+
+    // len(x): the same as `length of x`
+    env.define_builtin("len", Some(1), |_, args| {
+        let n = match &args[0] {
+            Value::Array(items) => items.borrow().len(),
+            Value::Record(fields) => fields.borrow().len(),
+            Value::Str(s) => s.chars().count(),
+            other => return Err(argument_error("len", 1, "an array, a record or a string", other)),
+        };
+        Ok(Value::Num(n as f64))
+    });
+}
+
+// collection helpers; the ones taking a function call it through the interpreter,
+// so callbacks get the same arity and annotation checks as any other call
+fn install_collections(env: &Environment) {
+    env.define_builtin("map", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("map", args)?;
+        let mut out = Vec::with_capacity(items.len());
+        for item in items {
+            out.push(callback(interp, "map", &f, vec![item])?);
+        }
+        Ok(Value::array(out))
+    });
+
+    env.define_builtin("filter", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("filter", args)?;
+        let mut out = Vec::new();
+        for item in items {
+            if test(interp, "filter", &f, item.clone())? {
+                out.push(item);
+            }
+        }
+        Ok(Value::array(out))
+    });
+
+    // reduce(list, __fn (total, item) ..., start)
+    env.define_builtin("reduce", Some(3), |interp, mut args| {
+        let start = args.pop().unwrap_or(Value::Null);
+        let (items, f) = list_and_callback("reduce", args)?;
+        let mut total = start;
+        for item in items {
+            total = callback(interp, "reduce", &f, vec![total, item])?;
+        }
+        Ok(total)
+    });
+
+    // a sorted copy, ordered by the number or string the callback gives for each item
+    env.define_builtin("sortBy", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("sortBy", args)?;
+        let mut keyed = Vec::with_capacity(items.len());
+        for item in items {
+            let key = callback(interp, "sortBy", &f, vec![item.clone()])?;
+            keyed.push((key, item));
+        }
+        let numbers = keyed.iter().all(|(k, _)| matches!(k, Value::Num(_)));
+        let strings = keyed.iter().all(|(k, _)| matches!(k, Value::Str(_)));
+        if !numbers && !strings {
+            let odd = keyed.iter().map(|(k, _)| k).find(|k| !matches!(k, Value::Num(_))).cloned();
+            let odd = odd.map(|k| describe(&k)).unwrap_or_default();
+            return Err(RuntimeError::new(E_TYPE, format!("`sortBy` keys must all be numbers or all be strings, got {}", odd)));
+        }
+        keyed.sort_by(|(a, _), (b, _)| match (a, b) {
+            (Value::Num(a), Value::Num(b)) => a.partial_cmp(b).unwrap_or(Ordering::Equal),
+            (Value::Str(a), Value::Str(b)) => a.cmp(b),
+            _ => Ordering::Equal,
+        });
+        Ok(Value::array(keyed.into_iter().map(|(_, item)| item).collect()))
+    });
+
+    // the first item the callback accepts, or null
+    env.define_builtin("find", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("find", args)?;
+        for item in items {
+            if test(interp, "find", &f, item.clone())? {
+                return Ok(item);
+            }
+        }
+        Ok(Value::Null)
+    });
+
+    env.define_builtin("any", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("any", args)?;
+        for item in items {
+            if test(interp, "any", &f, item)? {
+                return Ok(Value::Bool(true));
+            }
+        }
+        Ok(Value::Bool(false))
+    });
+
+    env.define_builtin("all", Some(2), |interp, args| {
+        let (items, f) = list_and_callback("all", args)?;
+        for item in items {
+            if !test(interp, "all", &f, item)? {
+                return Ok(Value::Bool(false));
+            }
+        }
+        Ok(Value::Bool(true))
+    });
+
+    // pairs up two lists, stopping at the end of the shorter one
+    env.define_builtin("zip", Some(2), |_, args| {
+        let a = list("zip", &args[0], 1)?;
+        let b = list("zip", &args[1], 2)?;
+        let pairs = a.into_iter().zip(b).map(|(x, y)| Value::array(vec![x, y])).collect();
+        Ok(Value::array(pairs))
+    });
+
+    // range(end), range(start, end) or range(start, end, step); `end` is excluded
+    env.define_builtin("range", None, |_, args| {
+        let mut nums = Vec::with_capacity(args.len());
+        for (i, arg) in args.iter().enumerate() {
+            match arg {
+                Value::Num(n) => nums.push(*n),
+                other => return Err(argument_error("range", i + 1, "a number", other)),
+            }
+        }
+        let (start, end, step) = match nums[..] {
+            [end] => (0.0, end, 1.0),
+            [start, end] => (start, end, 1.0),
+            [start, end, step] => (start, end, step),
+            _ => {
+                let msg = format!("`range` expects 1 to 3 arguments, got {}", args.len());
+                return Err(RuntimeError::new(E_ARITY, msg));
+            }
+        };
+        if step == 0.0 {
+            return Err(RuntimeError::new(E_TYPE, "`range` step cannot be 0"));
+        }
+        // counted up front: past 2^53 adding the step may not change the number at all
+        let count = ((end - start) / step).ceil().max(0.0);
+        if !count.is_finite() || count > MAX_RANGE {
+            return Err(RuntimeError::new(E_TYPE, format!("`range` from {} to {} in steps of {} is too long", start, end, step))
+                .with_note(format!("a range can hold at most {} numbers", MAX_RANGE)));
+        }
+        let out = (0..count as usize).map(|i| Value::Num(start + i as f64 * step)).collect();
+        Ok(Value::array(out))
+    });
+}
+
+// the most numbers `range` will make; more is almost surely a mistaken argument
+const MAX_RANGE: f64 = 10_000_000.0;
+
+pub fn argument_error(name: &str, position: usize, wanted: &str, got: &Value) -> RuntimeError {
+    let msg = format!("argument {} of `{}` must be {}, got {}", position, name, wanted, describe(got));
+    RuntimeError::new(E_TYPE, msg)
+}
+
+// a snapshot of an array argument, so callbacks may change the array while we walk it
+fn list(name: &str, v: &Value, position: usize) -> Result<Vec<Value>, RuntimeError> {
+    match v {
+        Value::Array(items) => Ok(items.borrow().clone()),
+        other => Err(argument_error(name, position, "an array", other)),
+    }
+}
+
+// the (list, function) arguments most helpers start with
+fn list_and_callback(name: &str, args: Vec<Value>) -> Result<(Vec<Value>, Value), RuntimeError> {
+    let items = list(name, &args[0], 1)?;
+    match &args[1] {
+        f @ (Value::Func(_) | Value::Builtin(_)) => Ok((items, f.clone())),
+        other => Err(argument_error(name, 2, "a function", other)),
+    }
+}
+
+fn callback(interp: &mut Interp, name: &str, f: &Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+    interp.call_value(&format!("{} callback", name), f.clone(), args)
+}
+
+// a callback that has to answer true or false
+fn test(interp: &mut Interp, name: &str, f: &Value, item: Value) -> Result<bool, RuntimeError> {
+    match callback(interp, name, f, vec![item])? {
+        Value::Bool(b) => Ok(b),
+        other => Err(RuntimeError::new(E_TYPE, format!("the callback of `{}` must return true or false, got {}", name, describe(&other)))),
+    }
+}
@@ -0,0 +1,733 @@
+use crate::ast::{Expr, ExprKind, Imported, Literal, Stmt, StmtKind, Type, TYPE_NAMES};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+// static checks over a parsed program, run before anything is executed.
+// types are inferred from literals, operators and function annotations;
+// whatever cannot be known without running the code is `any` and never
+// reported. an operation reported here fails whenever it runs, though it may
+// sit in code that never does; E0404 has no runtime counterpart at all, it
+// flags a function whose result cannot be relied on.
+
+// checker error codes
+const E_OPERAND: &str = "E0401";
+const E_ARITY: &str = "E0402";
+const E_ARG_TYPE: &str = "E0403";
+const E_RETURNS: &str = "E0404";
+const E_MISMATCH: &str = "E0405";
+const E_FLOW: &str = "E0406";
+
+// what the checker knows about a function name
+#[derive(Debug)]
+struct Signature {
+    params: Vec<String>,
+    types: Vec<(String, Type)>,
+    // the one type every `ret` produces, or `any`
+    ret: Type,
+}
+
+#[derive(Default)]
+struct Local {
+    ty: Option<Type>,
+    sig: Option<Rc<Signature>>,
+}
+
+pub fn check_program(program: &[Stmt]) -> Vec<Diagnostic> {
+    let mut captured = HashSet::new();
+    scan_stmts(program, &mut Writes::default(), &mut captured);
+    let mut checker = Checker { scopes: vec![HashMap::new()], returns: Vec::new(), captured, diagnostics: Vec::new() };
+
+    // like the interpreter, named top-level functions are visible everywhere
+    let (funcs, rest): (Vec<&Stmt>, Vec<&Stmt>) = program.iter().map(Stmt::unexported).partition(|s| {
+        matches!(&s.kind, StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(_), .. }, .. }))
+    });
+    for st in &funcs {
+        if let StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(name), params, types, .. }, .. }) = &st.kind {
+            checker.define_function(name, params, types, any());
+        }
+    }
+    for st in funcs.iter().chain(rest.iter()) {
+        checker.check_stmt(st);
+    }
+    checker.diagnostics
+}
+
+fn any() -> Type {
+    Type::Named("any".into())
+}
+
+fn named(name: &str) -> Type {
+    Type::Named(name.into())
+}
+
+fn is_any(t: &Type) -> bool {
+    *t == any()
+}
+
+// could a value of type `actual` be used where `expected` is wanted? a union or
+// optional value counts when any of its possibilities would do, since only
+// running the code can tell which one it is
+fn fits(actual: &Type, expected: &Type) -> bool {
+    match (actual, expected) {
+        _ if is_any(actual) || is_any(expected) => true,
+        (Type::Union(options), _) => options.iter().any(|t| fits(t, expected)),
+        (Type::Optional(a), _) => fits(a, expected) || fits(&named("null"), expected),
+        (_, Type::Union(options)) => options.iter().any(|t| fits(actual, t)),
+        (_, Type::Optional(e)) => *actual == named("null") || fits(actual, e),
+        (Type::Array(a), Type::Array(e)) => fits(a, e),
+        _ => actual == expected,
+    }
+}
+
+// predicates such as `integer` narrow a type in ways only running the code can
+// check, so in annotations they count as `any`
+fn checkable(t: &Type) -> Type {
+    match t {
+        Type::Named(n) if !TYPE_NAMES.contains(&n.as_str()) => any(),
+        Type::Array(elem) => Type::Array(Box::new(checkable(elem))),
+        Type::Optional(inner) => Type::Optional(Box::new(checkable(inner))),
+        Type::Union(options) => Type::Union(options.iter().map(checkable).collect()),
+        Type::Named(_) => t.clone(),
+    }
+}
+
+// the same type with nothing known about what its arrays hold. arrays are shared, so
+// once one is reachable from somewhere else anything may be stored in it
+fn loosen(t: &Type) -> Type {
+    match t {
+        Type::Array(_) => Type::Array(Box::new(any())),
+        Type::Optional(inner) => Type::Optional(Box::new(loosen(inner))),
+        Type::Union(options) => Type::Union(options.iter().map(loosen).collect()),
+        Type::Named(_) => t.clone(),
+    }
+}
+
+// the variable an expression like `grid at 0 at 1` reads from
+fn root_var(expr: &Expr) -> Option<&str> {
+    match &expr.kind {
+        ExprKind::Var(name) => Some(name),
+        ExprKind::Index { target, .. } => root_var(target),
+        _ => None,
+    }
+}
+
+// the names a function declares (its parameters and `let`s) and the ones it assigns to
+#[derive(Default)]
+struct Writes {
+    declared: HashSet<String>,
+    assigned: HashSet<String>,
+}
+
+// find the variables that functions assign to without declaring them. they belong to
+// an enclosing scope, and can change whenever the function is called, so the checker
+// never trusts their type. a function's own variables are scanned into `w`
+fn scan_stmts(stmts: &[Stmt], w: &mut Writes, captured: &mut HashSet<String>) {
+    for st in stmts {
+        match &st.kind {
+            StmtKind::Expr(e) | StmtKind::FunctionDef(e) | StmtKind::Import(e) => scan_expr(e, w, captured),
+            StmtKind::Let { name, value, .. } => {
+                w.declared.insert(name.clone());
+                scan_expr(value, w, captured);
+            }
+            StmtKind::Export(inner) => scan_stmts(std::slice::from_ref(&**inner), w, captured),
+            StmtKind::While { cond: e, body } | StmtKind::Repeat { count: e, body } => {
+                scan_expr(e, w, captured);
+                scan_stmts(body, w, captured);
+            }
+            StmtKind::Each { var, iter, body } => {
+                w.declared.insert(var.clone());
+                scan_expr(iter, w, captured);
+                scan_stmts(body, w, captured);
+            }
+            StmtKind::Stop | StmtKind::Skip => {}
+        }
+    }
+}
+
+fn scan_expr(expr: &Expr, w: &mut Writes, captured: &mut HashSet<String>) {
+    match &expr.kind {
+        ExprKind::Function { params, body, .. } => {
+            let mut inner = Writes { declared: params.iter().cloned().collect(), assigned: HashSet::new() };
+            scan_stmts(body, &mut inner, captured);
+            captured.extend(inner.assigned.difference(&inner.declared).cloned());
+        }
+        ExprKind::Assign { name, value } => {
+            w.assigned.insert(name.clone());
+            scan_expr(value, w, captured);
+        }
+        ExprKind::SetIndex { target, index, value } => {
+            if let Some(name) = root_var(target) {
+                w.assigned.insert(name.to_string());
+            }
+            for e in [target, index, value] {
+                scan_expr(e, w, captured);
+            }
+        }
+        ExprKind::Call { callee, args } => {
+            scan_expr(callee, w, captured);
+            for a in args {
+                scan_expr(a, w, captured);
+            }
+        }
+        ExprKind::If { cond, then_body, else_body } => {
+            scan_expr(cond, w, captured);
+            scan_stmts(then_body, w, captured);
+            if let Some(else_body) = else_body {
+                scan_stmts(else_body, w, captured);
+            }
+        }
+        ExprKind::Array(items) => {
+            for i in items {
+                scan_expr(i, w, captured);
+            }
+        }
+        ExprKind::Record(fields) => {
+            for (_, value) in fields {
+                scan_expr(value, w, captured);
+            }
+        }
+        ExprKind::Binary { left, right, .. } | ExprKind::Index { target: left, index: right } => {
+            scan_expr(left, w, captured);
+            scan_expr(right, w, captured);
+        }
+        ExprKind::SetField { target, value, .. } => {
+            scan_expr(target, w, captured);
+            scan_expr(value, w, captured);
+        }
+        ExprKind::Run(e) | ExprKind::Return(e) | ExprKind::Unary { expr: e, .. } | ExprKind::Is { expr: e, .. }
+        | ExprKind::Field { target: e, .. } => scan_expr(e, w, captured),
+        ExprKind::Var(_) | ExprKind::Lit(_) | ExprKind::Import { .. } => {}
+    }
+}
+
+// the type shared by all of `types`, or `any` when they differ (or there are none)
+fn common(types: &[Type]) -> Type {
+    match types.split_first() {
+        Some((first, rest)) if rest.iter().all(|t| t == first) => first.clone(),
+        _ => any(),
+    }
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Local>>,
+    // the types returned so far by each function being checked, innermost last
+    returns: Vec<Vec<(Type, Span)>>,
+    // variables some function assigns to from inside; see `scan_stmts`
+    captured: HashSet<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker {
+    fn lookup(&self, name: &str) -> Option<&Local> {
+        self.scopes.iter().rev().find_map(|s| s.get(name))
+    }
+
+    fn define(&mut self, name: &str, ty: Type) {
+        let ty = if self.captured.contains(name) { any() } else { ty };
+        let scope = self.scopes.last_mut().expect("global scope");
+        scope.insert(name.to_string(), Local { ty: Some(ty), sig: None });
+    }
+
+    fn define_function(&mut self, name: &str, params: &[String], types: &[(String, Type)], ret: Type) {
+        let types = types.iter().map(|(p, t)| (p.clone(), checkable(t))).collect();
+        let sig = Signature { params: params.to_vec(), types, ret };
+        let local = if self.captured.contains(name) {
+            Local { ty: Some(any()), sig: None }
+        } else {
+            Local { ty: Some(named("function")), sig: Some(Rc::new(sig)) }
+        };
+        let scope = self.scopes.last_mut().expect("global scope");
+        scope.insert(name.to_string(), local);
+    }
+
+    // a reassigned variable keeps its type only if the new value has the same one;
+    // a function that replaces another may take other arguments
+    fn assign(&mut self, name: &str, ty: Type) {
+        self.merge(name, Local { ty: Some(ty), sig: None });
+    }
+
+    // `name` may now hold what `new` describes, or what it held before
+    fn merge(&mut self, name: &str, new: Local) {
+        match self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name)) {
+            Some(local) => {
+                let same_sig = match (&local.sig, &new.sig) {
+                    (Some(a), Some(b)) => Rc::ptr_eq(a, b),
+                    (a, b) => a.is_none() && b.is_none(),
+                };
+                if local.ty != new.ty || !same_sig {
+                    *local = Local { ty: Some(any()), sig: None };
+                }
+            }
+            None => match new.sig {
+                Some(_) => {
+                    self.scopes.last_mut().expect("global scope").insert(name.to_string(), new);
+                }
+                None => self.define(name, new.ty.unwrap_or_else(any)),
+            },
+        }
+    }
+
+    // the value of `expr` may now be reached through another name, which can store
+    // anything in its arrays
+    fn share(&mut self, expr: &Expr, ty: Type) -> Type {
+        let shared = loosen(&ty);
+        if shared != ty {
+            if let Some(local) = root_var(expr).and_then(|name| self.scopes.iter_mut().rev().find_map(|s| s.get_mut(name))) {
+                local.ty = local.ty.as_ref().map(loosen);
+            }
+        }
+        shared
+    }
+
+    fn report(&mut self, d: Diagnostic) {
+        self.diagnostics.push(d);
+    }
+
+    fn check_block(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for st in stmts {
+            self.check_stmt(st);
+        }
+        self.scopes.pop();
+    }
+
+    // a loop body runs again after its own assignments, so before it is checked
+    // every variable it assigns to may already hold anything
+    fn forget_assigned(&mut self, body: &[Stmt]) {
+        let mut writes = Writes::default();
+        // functions defined in the body were scanned into `captured` already
+        scan_stmts(body, &mut writes, &mut HashSet::new());
+        for name in writes.assigned {
+            if let Some(local) = self.scopes.iter_mut().rev().find_map(|s| s.get_mut(&name)) {
+                *local = Local { ty: Some(any()), sig: None };
+            }
+        }
+    }
+
+    // unlike loops, the branches of an `if` run in the enclosing scope, but they might
+    // not run at all: what they define keeps its type only if it had that type before
+    fn check_branch(&mut self, stmts: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        for st in stmts {
+            self.check_stmt(st);
+        }
+        let defined = self.scopes.pop().unwrap_or_default();
+        for (name, local) in defined {
+            self.merge(&name, local);
+        }
+    }
+
+    fn check_stmt(&mut self, stmt: &Stmt) {
+        match &stmt.kind {
+            StmtKind::Expr(expr) => {
+                self.infer(expr);
+            }
+            StmtKind::FunctionDef(f) => {
+                self.infer(f);
+            }
+            StmtKind::Let { name, value, .. } => {
+                let ty = self.infer(value);
+                let ty = self.share(value, ty);
+                self.define(name, ty);
+            }
+            StmtKind::Import(Expr { kind: ExprKind::Import { what, module }, .. }) => match what {
+                Imported::Names(names) => {
+                    for (name, alias) in names {
+                        self.define(alias.as_ref().unwrap_or(name), any());
+                    }
+                }
+                Imported::Module(alias) => self.define(alias.as_ref().unwrap_or(module), any()),
+                // the names are unknown until the module runs, and unknown names are `any` anyway
+                Imported::All => {}
+            },
+            StmtKind::Export(inner) => self.check_stmt(inner),
+            StmtKind::While { cond, body } => {
+                // the condition is tested again after every pass
+                self.forget_assigned(body);
+                self.expect_type(cond, &named("bool"), "a `while` condition");
+                self.check_block(body);
+            }
+            StmtKind::Repeat { count, body } => {
+                self.expect_type(count, &named("number"), "a `repeat` count");
+                self.forget_assigned(body);
+                self.check_block(body);
+            }
+            StmtKind::Each { var, iter, body } => {
+                let item = match self.infer(iter) {
+                    Type::Array(elem) => *elem,
+                    t if t == named("string") => t,
+                    t if t == named("record") => named("string"),
+                    t if is_any(&t) || matches!(t, Type::Union(_) | Type::Optional(_)) => any(),
+                    t => {
+                        self.report(Diagnostic::error(E_MISMATCH, format!("cannot loop over {}", t), iter.span)
+                            .with_label("this is not an array, a record or a string"));
+                        any()
+                    }
+                };
+                self.forget_assigned(body);
+                self.scopes.push(HashMap::new());
+                self.define(var, item);
+                self.check_block(body);
+                self.scopes.pop();
+            }
+            // the parser only ever puts an `ExprKind::Import` in an import statement
+            StmtKind::Import(_) | StmtKind::Stop | StmtKind::Skip => {}
+        }
+    }
+
+    fn expect_type(&mut self, expr: &Expr, expected: &Type, what: &str) {
+        let ty = self.infer(expr);
+        if !fits(&ty, expected) {
+            self.report(Diagnostic::error(E_MISMATCH, format!("{} must be {}, found {}", what, expected, ty), expr.span)
+                .with_label(format!("this is {}", ty)));
+        }
+    }
+
+    fn expect_record(&mut self, target: &Expr, field: &str) {
+        let ty = self.infer(target);
+        if !fits(&ty, &named("record")) {
+            self.report(Diagnostic::error(E_MISMATCH, format!("cannot use field `{}` of {}", field, ty), target.span)
+                .with_label(format!("this is {}, not a record", ty)));
+        }
+    }
+
+    fn infer(&mut self, expr: &Expr) -> Type {
+        match &expr.kind {
+            ExprKind::Lit(lit) => match lit {
+                Literal::Str(_) => named("string"),
+                Literal::Num(_) => named("number"),
+                Literal::Bool(_) => named("bool"),
+                Literal::Null => named("null"),
+            },
+            ExprKind::Array(items) => {
+                let types: Vec<Type> = items.iter().map(|i| self.infer(i)).collect();
+                Type::Array(Box::new(common(&types)))
+            }
+            ExprKind::Index { target, index } => {
+                let t = self.infer(target);
+                self.expect_type(index, &named("number"), "an index");
+                match t {
+                    Type::Array(elem) => *elem,
+                    t if t == named("string") => t,
+                    t if is_any(&t) || matches!(t, Type::Union(_) | Type::Optional(_)) => any(),
+                    t => {
+                        self.report(Diagnostic::error(E_MISMATCH, format!("cannot index into {}", t), target.span)
+                            .with_label(format!("this is {}", t)));
+                        any()
+                    }
+                }
+            }
+            ExprKind::SetIndex { target, index, value } => {
+                let t = self.infer(target);
+                self.expect_type(index, &named("number"), "an index");
+                let ty = self.infer(value);
+                match t {
+                    // storing a different kind of value widens what the array holds
+                    Type::Array(elem) => {
+                        if *elem != ty {
+                            self.share(target, Type::Array(elem));
+                        }
+                    }
+                    t if is_any(&t) || matches!(t, Type::Union(_) | Type::Optional(_)) => {}
+                    t => {
+                        self.report(Diagnostic::error(E_MISMATCH, format!("cannot assign to an element of {}", t), target.span)
+                            .with_label(format!("this is {}", t)));
+                    }
+                }
+                ty
+            }
+            ExprKind::Var(name) => self.lookup(name).and_then(|l| l.ty.clone()).unwrap_or_else(any),
+            ExprKind::Binary { left, op, right } => {
+                let l = self.infer(left);
+                let r = self.infer(right);
+                self.binary(op, l, r, expr.span)
+            }
+            ExprKind::Is { expr: inner, .. } => {
+                self.infer(inner);
+                named("bool")
+            }
+            ExprKind::Record(fields) => {
+                for (_, value) in fields {
+                    self.infer(value);
+                }
+                named("record")
+            }
+            ExprKind::Field { target, name } => {
+                self.expect_record(target, name);
+                any()
+            }
+            ExprKind::SetField { target, name, value } => {
+                self.expect_record(target, name);
+                self.infer(value)
+            }
+            ExprKind::Unary { op, expr: inner } => {
+                let (wanted, result) = match op.as_str() {
+                    "not" => (named("bool"), named("bool")),
+                    "length" => {
+                        let sized = vec![Type::Array(Box::new(any())), named("record"), named("string")];
+                        (Type::Union(sized), named("number"))
+                    }
+                    _ => (named("number"), named("number")),
+                };
+                let ty = self.infer(inner);
+                if !fits(&ty, &wanted) {
+                    self.report(Diagnostic::error(E_OPERAND, format!("`{}` needs {}, found {}", op, wanted, ty), inner.span)
+                        .with_label(format!("this is {}", ty)));
+                }
+                result
+            }
+            ExprKind::Assign { name, value } => {
+                let ty = self.infer(value);
+                let ty = self.share(value, ty);
+                self.assign(name, ty.clone());
+                ty
+            }
+            ExprKind::If { cond, then_body, else_body } => {
+                self.expect_type(cond, &named("bool"), "an `if` condition");
+                self.check_branch(then_body);
+                if let Some(else_body) = else_body {
+                    self.check_branch(else_body);
+                }
+                named("null")
+            }
+            ExprKind::Run(inner) => self.infer(inner),
+            ExprKind::Return(value) => {
+                let ty = self.infer(value);
+                match self.returns.last_mut() {
+                    Some(returns) => returns.push((ty.clone(), expr.span)),
+                    None => self.report(
+                        Diagnostic::error(E_FLOW, "`ret` used outside of a function", expr.span)
+                            .with_label("there is no function to return from"),
+                    ),
+                }
+                ty
+            }
+            ExprKind::Call { callee, args } => {
+                let arg_types: Vec<Type> = args.iter().map(|a| self.infer(a)).collect();
+                // the function may store anything in the arrays it is given
+                for (a, t) in args.iter().zip(&arg_types) {
+                    self.share(a, t.clone());
+                }
+                let sig = match &callee.kind {
+                    ExprKind::Var(name) => self.lookup(name).and_then(|l| l.sig.clone()).map(|s| (name.clone(), s)),
+                    _ => {
+                        self.infer(callee);
+                        None
+                    }
+                };
+                match sig {
+                    Some((name, sig)) => self.check_call(&name, &sig, args, &arg_types, expr.span),
+                    None => any(),
+                }
+            }
+            ExprKind::Function { name, params, types, body } => {
+                let ret = self.check_function(params, types, body);
+                if let Some(name) = name {
+                    self.define_function(name, params, types, ret);
+                }
+                named("function")
+            }
+            ExprKind::Import { .. } => any(),
+        }
+    }
+
+    fn check_function(&mut self, params: &[String], types: &[(String, Type)], body: &[Stmt]) -> Type {
+        self.scopes.push(HashMap::new());
+        for p in params {
+            let ty = types.iter().find(|(n, _)| n == p).map(|(_, t)| checkable(t)).unwrap_or_else(any);
+            self.define(p, ty);
+        }
+        self.returns.push(Vec::new());
+        self.check_block(body);
+        let returns = self.returns.pop().unwrap_or_default();
+        self.scopes.pop();
+
+        // every `ret` whose type is known has to agree with the first one
+        let mut known = returns.into_iter().filter(|(t, _)| !is_any(t));
+        let Some((first, first_span)) = known.next() else { return any() };
+        let mut consistent = true;
+        for (ty, span) in known {
+            if !fits(&ty, &first) && !fits(&first, &ty) {
+                consistent = false;
+                self.report(Diagnostic::error(E_RETURNS, format!("this function returns both {} and {}", first, ty), span)
+                    .with_label(format!("returns {} here", ty))
+                    .with_secondary(first_span, format!("but {} here", first)));
+            }
+        }
+        if consistent { first } else { any() }
+    }
+
+    fn check_call(&mut self, name: &str, sig: &Signature, args: &[Expr], arg_types: &[Type], span: Span) -> Type {
+        if sig.params.len() != args.len() {
+            let expected = sig.params.len();
+            self.report(Diagnostic::error(
+                E_ARITY,
+                format!("`{}` expects {} argument{}, got {}", name, expected, if expected == 1 { "" } else { "s" }, args.len()),
+                span,
+            )
+            .with_label(format!("called with {}", args.len())));
+            return sig.ret.clone();
+        }
+        for (param, expected) in &sig.types {
+            let Some(i) = sig.params.iter().position(|p| p == param) else { continue };
+            if !fits(&arg_types[i], expected) {
+                self.report(Diagnostic::error(
+                    E_ARG_TYPE,
+                    format!("argument `{}` of `{}` must be {}, found {}", param, name, expected, arg_types[i]),
+                    args[i].span,
+                )
+                .with_label(format!("this is {}", arg_types[i])));
+            }
+        }
+        sig.ret.clone()
+    }
+
+    fn binary(&mut self, op: &str, l: Type, r: Type, span: Span) -> Type {
+        let (number, string, boolean) = (named("number"), named("string"), named("bool"));
+        let result = match op {
+            "plus" => {
+                let addable = Type::Union(vec![number.clone(), string.clone(), Type::Array(Box::new(any()))]);
+                match (&l, &r) {
+                    _ if is_any(&l) || is_any(&r) => (fits(&l, &addable) && fits(&r, &addable)).then(any),
+                    (Type::Union(_) | Type::Optional(_), _) | (_, Type::Union(_) | Type::Optional(_)) => Some(any()),
+                    (Type::Array(a), Type::Array(b)) => Some(Type::Array(Box::new(common(&[(**a).clone(), (**b).clone()])))),
+                    _ if l == number && r == number => Some(number),
+                    _ if l == string && (r == number || r == string) => Some(string),
+                    _ => None,
+                }
+            }
+            "minus" | "times" | "divided_by" | "mod" => (fits(&l, &number) && fits(&r, &number)).then_some(number),
+            "and" | "or" => (fits(&l, &boolean) && fits(&r, &boolean)).then_some(boolean),
+            "greater_than" | "less_than" | "at_least" | "at_most" => {
+                let ordered = (fits(&l, &number) && fits(&r, &number)) || (fits(&l, &string) && fits(&r, &string));
+                ordered.then_some(boolean)
+            }
+            // same and not_equal compare anything
+            _ => Some(boolean),
+        };
+        result.unwrap_or_else(|| {
+            self.report(Diagnostic::error(E_OPERAND, format!("`{}` cannot be used with {} and {}", op.replace('_', " "), l, r), span)
+                .with_label(format!("{} {} {}", l, op.replace('_', " "), r)));
+            any()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::check_program;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    // the codes of everything the checker reports about a program without syntax errors
+    fn codes(code: &str) -> Vec<String> {
+        let (toks, lex_diags) = Lexer::new(code).tokenize();
+        let (prog, parse_diags) = Parser::new(toks).parse_program();
+        assert!(lex_diags.is_empty() && parse_diags.is_empty(), "{:?} {:?}", lex_diags, parse_diags);
+        check_program(&prog).into_iter().map(|d| d.code).collect()
+    }
+
+    #[test]
+    fn operand_of_the_wrong_type() {
+        assert_eq!(codes("let s = \"a\"\nprint(s times 2)\n"), ["E0401"]);
+        assert_eq!(codes("print(not 3)\n"), ["E0401"]);
+        assert_eq!(codes("print(length of 3)\n"), ["E0401"]);
+        assert_eq!(codes("print(\"a\" plus 1)\nprint(1 plus \"a\")\n"), ["E0401"]);
+    }
+
+    #[test]
+    fn wrong_number_of_arguments() {
+        assert_eq!(codes("__fn f = (a)\n  ret a\n__\nf(1, 2)\nf()\nf(1)\n"), ["E0402", "E0402"]);
+    }
+
+    #[test]
+    fn argument_of_the_wrong_type() {
+        let code = "__fn twice = (n): <n is number>\n  ret n times 2\n__\ntwice(\"a\")\ntwice(2)\n";
+        assert_eq!(codes(code), ["E0403"]);
+        // predicates are only checked when the code runs
+        let code = "__fn half = (n): <n is integer>\n  ret n divided by 2\n__\nhalf(\"a\")\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn inconsistent_returns() {
+        let code = "__fn f = (a)\n  if a - then,\n    ret 1\n  __\n  ret \"none\"\n__\n";
+        assert_eq!(codes(code), ["E0404"]);
+        let code = "__fn f = (a)\n  if a - then,\n    ret 1\n  __\n  ret 2\n__\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn value_of_the_wrong_type() {
+        assert_eq!(codes("while 1 do\n  stop\n__\n"), ["E0405"]);
+        assert_eq!(codes("let n = 3\nprint(n at 0)\n"), ["E0405"]);
+        assert_eq!(codes("each c in 5 do\n  print(c)\n__\n"), ["E0405"]);
+    }
+
+    #[test]
+    fn return_outside_a_function() {
+        assert_eq!(codes("ret 1\n"), ["E0406"]);
+    }
+
+    #[test]
+    fn unknown_types_are_never_reported() {
+        assert_eq!(codes("__fn f = (a)\n  ret a\n__\nprint(f(1) times 2)\nprint(args at 0 plus 1)\n"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn assignment_in_a_function_forgets_the_type() {
+        let code = "let count = \"none\"\n__fn bump = ()\n  count = 3\n__\nbump()\nprint(count times 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn array_passed_to_a_function_forgets_its_elements() {
+        let code = "let xs = [\"a\"]\n__fn poke = (l)\n  l at 0 = 5\n__\npoke(xs)\nprint(xs at 0 times 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn array_reached_through_another_name_forgets_its_elements() {
+        let code = "let xs = [\"a\"]\nlet ys = xs\nys at 0 = 5\nprint(xs at 0 times 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+        let code = "let grid = [[\"a\"]]\ngrid at 0 at 0 = 5\nprint(grid at 0 at 0 times 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn let_in_an_if_branch_may_replace_the_variable() {
+        let code = "let x = \"s\"\nif true - then,\n  let x = 5\n__\nprint(x times 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn let_in_an_if_branch_is_visible_after_it() {
+        let code = "if true - then,\n  let y = \"a\"\n__\nprint(y times 2)\n";
+        assert_eq!(codes(code), ["E0401"]);
+    }
+
+    #[test]
+    fn assignment_later_in_a_loop_is_seen_on_the_next_pass() {
+        let code = "let best = null\neach x in [3, 7, 2] - do,\n  if best same null or x greater than best - then,\n    best = x\n  __\n__\nprint(best)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+        let code = "let x = \"none\"\nlet first = true\nrepeat 2 - do,\n  if not first - then,\n    print(x times 2)\n  __\n  x = 5\n  first = false\n__\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+        let code = "let n = \"a\"\nwhile n less than 3 - do,\n  n = 4\n__\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+
+    #[test]
+    fn optional_array_can_be_looped_over() {
+        let code = "__fn f = (a): <a is optional number Array>\n  each x in a - do,\n    print(x)\n  __\n__\nf([1])\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+        assert_eq!(codes("each x in 5 - do,\n  print(x)\n__\n"), ["E0405"]);
+    }
+
+    #[test]
+    fn replaced_function_is_not_held_to_its_old_arity() {
+        let code = "__fn f = (a)\n  ret a\n__\n__fn g = (a, b)\n  ret a\n__\nf = g\nf(1, 2)\n";
+        assert_eq!(codes(code), Vec::<String>::new());
+    }
+}
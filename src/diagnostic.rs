@@ -0,0 +1,185 @@
+use crate::span::Span;
+use std::fmt::Write;
+use std::path::PathBuf;
+
+// error codes by stage:
+//   E01xx lexer, E02xx parser, E03xx runtime, E04xx static checker
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+// the text of a program and the name it is reported under; `path` is set when
+// it was read from a file, and is where its imports are looked up from
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    pub code: String,
+    pub path: Option<PathBuf>,
+}
+
+// a span plus the short text printed next to its underline
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub primary: Label,
+    pub secondary: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(code: &str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: code.to_string(),
+            message: message.into(),
+            primary: Label { span, text: String::new() },
+            secondary: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    pub fn warning(code: &str, message: impl Into<String>, span: Span) -> Self {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::error(code, message, span) }
+    }
+
+    // text shown under the primary carets
+    pub fn with_label(mut self, text: impl Into<String>) -> Self {
+        self.primary.text = text.into();
+        self
+    }
+
+    pub fn with_secondary(mut self, span: Span, text: impl Into<String>) -> Self {
+        self.secondary.push(Label { span, text: text.into() });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    // rustc-style report: header, location, the offending source line(s) with
+    // carets under the primary span and dashes under secondary spans, then notes
+    pub fn render(&self, source: &str, file: &str) -> String {
+        let mut out = String::new();
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        let _ = writeln!(out, "{}[{}]: {}", severity, self.code, self.message);
+
+        let p = self.primary.span;
+        let last_line = self.secondary.iter().map(|l| l.span.line).fold(p.line, usize::max);
+        let width = last_line.to_string().len();
+        let pad = " ".repeat(width);
+        let _ = writeln!(out, "{}--> {}:{}:{}", pad, file, p.line, p.col);
+        let _ = writeln!(out, "{} |", pad);
+
+        // labels are shown in source order, whichever one is primary, and the labels
+        // of one line all go under a single copy of it
+        let mut labels: Vec<(&Label, char)> = vec![(&self.primary, '^')];
+        labels.extend(self.secondary.iter().map(|l| (l, '-')));
+        labels.sort_by_key(|(l, _)| l.span.start);
+        for group in labels.chunk_by(|(a, _), (b, _)| a.span.line == b.span.line) {
+            render_line(&mut out, source, group, width);
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            let _ = writeln!(out, "{} |", pad);
+        }
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = note: {}", pad, note);
+        }
+        if let Some(help) = &self.help {
+            let _ = writeln!(out, "{} = help: {}", pad, help);
+        }
+        out
+    }
+}
+
+// one source line, then an underline for each of the labels that start on it
+fn render_line(out: &mut String, source: &str, labels: &[(&Label, char)], width: usize) {
+    let line = labels[0].0.span.line;
+    let line_text = source.lines().nth(line.saturating_sub(1)).unwrap_or("");
+    let _ = writeln!(out, "{:>width$} | {}", line, line_text, width = width);
+    for (label, mark) in labels {
+        let span = label.span;
+        // underline the part of the span that is on its first line (at least one column)
+        let covered = source.get(span.start..span.end.max(span.start)).unwrap_or("");
+        let first_line = covered.split('\n').next().unwrap_or("");
+        let len = first_line.chars().count().max(1);
+        let _ = write!(
+            out,
+            "{} | {}{}",
+            " ".repeat(width),
+            " ".repeat(span.col.saturating_sub(1)),
+            mark.to_string().repeat(len)
+        );
+        if label.text.is_empty() {
+            out.push('\n');
+        } else {
+            let _ = writeln!(out, " {}", label.text);
+        }
+    }
+}
+
+// print diagnostics to stderr; true if any of them is an error
+pub fn report(diags: &[Diagnostic], src: &Source) -> bool {
+    for d in diags {
+        eprintln!("{}", d.render(&src.code, &src.name));
+    }
+    diags.iter().any(Diagnostic::is_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostic;
+    use crate::span::Span;
+
+    #[test]
+    fn labels_on_one_line_share_it() {
+        let code = "print(range(1, 2)\nprint(3)\n";
+        let d = Diagnostic::error("E0201", "expected `)`", Span { start: 17, end: 18, line: 1, col: 18 })
+            .with_label("expected `,` or `)`")
+            .with_secondary(Span { start: 5, end: 6, line: 1, col: 6 }, "opened here");
+        let expected = "\
+error[E0201]: expected `)`
+ --> main.zt:1:18
+  |
+1 | print(range(1, 2)
+  |      - opened here
+  |                  ^ expected `,` or `)`
+";
+        assert_eq!(d.render(code, "main.zt"), expected);
+    }
+
+    #[test]
+    fn labels_on_different_lines_show_each_line() {
+        let code = "__fn f = ()\n  ret 1\n";
+        let d = Diagnostic::error("E0202", "unclosed function", Span { start: 20, end: 20, line: 3, col: 1 })
+            .with_secondary(Span { start: 0, end: 4, line: 1, col: 1 }, "function starts here");
+        let rendered = d.render(code, "main.zt");
+        assert!(rendered.contains("1 | __fn f = ()\n  | ---- function starts here\n3 | \n  | ^\n"), "{}", rendered);
+    }
+}
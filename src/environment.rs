@@ -1,149 +1,246 @@
-use crate::ast::{Stmt, Expr, Literal};
-use crate::environment::{Environment, Value, Function};
+use crate::ast::{Literal, Stmt, Type};
+use crate::diagnostic::Source;
+use crate::interpreter::{Interp, RuntimeError};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::rc::Rc;
 
-pub fn interpret(program: Vec<Stmt>, env: &Environment) {
-    for s in program {
-        match s {
-            Stmt::ImportStmt(e) => {
-                if let Expr::Import { name, alias, module } = e {
-                    // For prototype: simulate import by registering a dummy function or builtin
-                    // e.g., "http_request" provides "request" which returns string "ok"
-                    if module == "http_request" {
-                        env.set(&alias.unwrap_or(name.clone()), Value::Func(Function {
-                            params: vec!["url".into()],
-                            body: vec![],
-                            types: vec![],
-                        }));
-                    } else {
-                        // unknown module: store Null
-                        env.set(&alias.unwrap_or(name.clone()), Value::Null);
-                    }
-                }
+// a user defined function: parameter names, optional type annotations, the body
+// and the scope it was defined in, which its calls run inside of
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<String>,
+    pub body: Vec<Stmt>,
+    pub types: Vec<(String, Type)>,
+    pub closure: Environment,
+    // the code the function was written in, for error messages; None when unknown
+    pub source: Option<Rc<Source>>,
+}
+
+// the rust side of a builtin; it gets the interpreter so it can call back into zeet functions
+pub type NativeFn = Rc<dyn Fn(&mut Interp, Vec<Value>) -> Result<Value, RuntimeError>>;
+
+// a function implemented in rust; arity of None means "any number of arguments"
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: String,
+    pub arity: Option<usize>,
+    pub func: NativeFn,
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum Value {
+    Null,
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    // shared: every copy of an array value sees the same elements
+    Array(Rc<RefCell<Vec<Value>>>),
+    // named fields, shared the same way as arrays and kept in key order
+    Record(Rc<RefCell<BTreeMap<String, Value>>>),
+    Func(Rc<Function>),
+    Builtin(Builtin),
+}
+
+impl Value {
+    pub fn array(items: Vec<Value>) -> Value {
+        Value::Array(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn record(fields: BTreeMap<String, Value>) -> Value {
+        Value::Record(Rc::new(RefCell::new(fields)))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Num(_) => "number",
+            Value::Str(_) => "string",
+            Value::Bool(_) => "bool",
+            Value::Array(_) => "array",
+            Value::Record(_) => "record",
+            Value::Func(_) => "function",
+            Value::Builtin(_) => "builtin function",
+        }
+    }
+}
+
+impl From<Literal> for Value {
+    fn from(lit: Literal) -> Self {
+        match lit {
+            Literal::Str(s) => Value::Str(s),
+            Literal::Num(n) => Value::Num(n),
+            Literal::Bool(b) => Value::Bool(b),
+            Literal::Null => Value::Null,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_value(self, f, &mut Vec::new())
+    }
+}
+
+// `inside` holds the arrays and records being written around this value; an array can
+// contain itself (`a at 0 = a`), and is then written as `[...]` the second time
+fn write_value(v: &Value, f: &mut fmt::Formatter, inside: &mut Vec<*const ()>) -> fmt::Result {
+    match v {
+        Value::Null => write!(f, "null"),
+        Value::Num(n) => write!(f, "{}", n),
+        Value::Str(s) => write!(f, "{}", s),
+        Value::Bool(b) => write!(f, "{}", b),
+        Value::Array(items) => {
+            let id = Rc::as_ptr(items) as *const ();
+            if inside.contains(&id) {
+                return write!(f, "[...]");
             }
-            Stmt::FunctionDef(e) => {
-                if let Expr::Function { params, types, body } = e {
-                    // store anonymous function under special name? For now require user to assign externally.
-                    // For prototype let's register with a generated name or allow retrieving by index.
-                    // Simpler: store function under name "__anonN" not ideal but enough for demonstration.
-                    let fn_name = format!("__fn_{}", rand::random::<u32>());
-                    env.set(&fn_name, Value::Func(Function { params, body, types }));
-                    // print registration
-                    println!("Registered function {}", fn_name);
-                }
+            inside.push(id);
+            write!(f, "[")?;
+            for (i, item) in items.borrow().iter().enumerate() {
+                if i > 0 { write!(f, ", ")?; }
+                write_value(item, f, inside)?;
             }
-            Stmt::Expr(expr) => {
-                eval_expr(expr, env);
+            inside.pop();
+            write!(f, "]")
+        }
+        Value::Record(fields) => {
+            let id = Rc::as_ptr(fields) as *const ();
+            if inside.contains(&id) {
+                return write!(f, "{{...}}");
             }
-            _ => {}
+            inside.push(id);
+            write!(f, "{{")?;
+            for (i, (key, value)) in fields.borrow().iter().enumerate() {
+                if i > 0 { write!(f, ", ")?; }
+                write!(f, "{}: ", key)?;
+                write_value(value, f, inside)?;
+            }
+            inside.pop();
+            write!(f, "}}")
         }
+        Value::Func(func) => write!(f, "<fn({})>", func.params.join(", ")),
+        Value::Builtin(b) => write!(f, "<builtin {}>", b.name),
+    }
+}
+
+// why an assignment was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum AssignError {
+    Undefined,
+    Constant,
+}
+
+// one level of variable bindings plus a link to the enclosing scope
+#[derive(Default)]
+struct Scope {
+    vars: RefCell<HashMap<String, Value>>,
+    consts: RefCell<HashSet<String>>,
+    parent: Option<Environment>,
+}
+
+// a cheap, cloneable handle to a scope; clones share the same bindings
+#[derive(Clone, Default)]
+pub struct Environment {
+    scope: Rc<Scope>,
+}
+
+// scopes can be cyclic (a function captures the scope that holds it), so only name them
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Environment({} names)", self.scope.vars.borrow().len())
     }
 }
 
-fn eval_expr(expr: Expr, env: &Environment) -> Value {
-    match expr {
-        Expr::Lit(l) => Value::from(l),
-        Expr::Var(name) => {
-            env.get(&name).unwrap_or(Value::Null)
+impl Environment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // expose a rust function to programs under `name`
+    pub fn define_builtin<F>(&self, name: &str, arity: Option<usize>, func: F)
+    where
+        F: Fn(&mut Interp, Vec<Value>) -> Result<Value, RuntimeError> + 'static,
+    {
+        let builtin = Builtin { name: name.to_string(), arity, func: Rc::new(func) };
+        self.define(name, Value::Builtin(builtin));
+    }
+
+    // create a nested scope whose lookups fall back to `self`
+    pub fn child(&self) -> Self {
+        Environment {
+            scope: Rc::new(Scope { parent: Some(self.clone()), ..Scope::default() }),
         }
-        Expr::Binary { left, op, right } => {
-            let l = eval_expr(*left, env);
-            let r = eval_expr(*right, env);
-            match op.as_str() {
-                "plus" => {
-                    match (l, r) {
-                        (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
-                        (Value::Str(a), Value::Num(b)) => {
-                            Value::Str(format!("{}{}", a, b))
-                        }
-                        (Value::Str(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
-                        _ => Value::Null,
-                    }
-                }
-                "and" => {
-                    match (l, r) {
-                        (Value::Bool(a), Value::Bool(b)) => Value::Bool(a && b),
-                        _ => Value::Null,
-                    }
-                }
-                "same" => {
-                    // check string equality or number equality
-                    match (l, r) {
-                        (Value::Str(a), Value::Str(b)) => Value::Bool(a == b),
-                        (Value::Num(a), Value::Num(b)) => Value::Bool((a - b).abs() < 1e-9),
-                        _ => Value::Bool(false),
-                    }
-                }
-                "not_equal" => {
-                    match (l, r) {
-                        (Value::Num(a), Value::Num(b)) => Value::Bool((a - b).abs() > 1e-9),
-                        (Value::Str(a), Value::Str(b)) => Value::Bool(a != b),
-                        _ => Value::Bool(true),
-                    }
-                }
-                _ => Value::Null,
-            }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        if let Some(v) = self.scope.vars.borrow().get(name) {
+            return Some(v.clone());
         }
-        Expr::If { cond, then_body, else_body } => {
-            let c = eval_expr(*cond, env);
-            let take_then = match c {
-                Value::Bool(b) => b,
-                _ => false,
-            };
-            if take_then {
-                for st in then_body {
-                    if let Stmt::Expr(e) = st {
-                        let val = eval_expr(e, env);
-                        // return on Return
-                        if let Value::Null = val { } // ignore
-                    }
-                }
-            } else if let Some(else_block) = else_body {
-                for st in else_block {
-                    if let Stmt::Expr(e) = st {
-                        let val = eval_expr(e, env);
-                    }
-                }
-            }
-            Value::Null
+        self.scope.parent.as_ref().and_then(|p| p.get(name))
+    }
+
+    // bind `name` in this scope, shadowing any outer binding. this is for scopes that
+    // are being filled in (parameters, loop variables, builtins); `declare` is the one
+    // that respects constants
+    pub fn define(&self, name: &str, value: Value) {
+        self.scope.vars.borrow_mut().insert(name.to_string(), value);
+    }
+
+    // `let` / `const`: like define, but a constant in this same scope cannot be redeclared
+    pub fn declare(&self, name: &str, value: Value, constant: bool) -> Result<(), AssignError> {
+        if self.scope.consts.borrow().contains(name) {
+            return Err(AssignError::Constant);
         }
-        Expr::Run(boxed) => {
-            // handle as a function call where callee is Var(name) or Call
-            match *boxed {
-                Expr::Call{ callee, args } => {
-                    // not implemented heavy: return Null
-                    Value::Null
-                }
-                Expr::Var(name) => {
-                    // call builtin 'add' or imported functions: for prototype, if name == "add" and args absent, return dummy
-                    if name == "add" {
-                        Value::Num(42.0)
-                    } else {
-                        env.get(&name).unwrap_or(Value::Null)
-                    }
-                }
-                _ => Value::Null,
+        self.define(name, value);
+        if constant {
+            self.scope.consts.borrow_mut().insert(name.to_string());
+        }
+        Ok(())
+    }
+
+    // update an existing binding, walking outwards through the parent chain
+    pub fn assign(&self, name: &str, value: Value) -> Result<(), AssignError> {
+        if let Some(slot) = self.scope.vars.borrow_mut().get_mut(name) {
+            if self.scope.consts.borrow().contains(name) {
+                return Err(AssignError::Constant);
             }
+            *slot = value;
+            return Ok(());
         }
-        Expr::Return(boxed) => {
-            // return expression value: here we just evaluate and print result
-            let v = eval_expr(*boxed, env);
-            println!("Return => {:?}", v);
-            v
+        match &self.scope.parent {
+            Some(p) => p.assign(name, value),
+            None => Err(AssignError::Undefined),
         }
-        Expr::Call { callee, args } => {
-            // support simple form: callee is Var(name)
-            if let Expr::Var(name) = *callee {
-                // find in env
-                if let Some(Value::Func(f)) = env.get(&name) {
-                    // for prototype: do not execute body, just return Null or dummy
-                    return Value::Null;
-                } else {
-                    return Value::Null;
-                }
+    }
+
+    // assign if the name exists somewhere in the chain, otherwise define it here
+    pub fn set(&self, name: &str, value: Value) -> Result<(), AssignError> {
+        match self.assign(name, value.clone()) {
+            Err(AssignError::Undefined) => {
+                self.define(name, value);
+                Ok(())
             }
-            Value::Null
+            other => other,
         }
-        _ => Value::Null,
     }
-}
\ No newline at end of file
+
+    // a name bound in this scope itself, ignoring its parents
+    pub fn get_local(&self, name: &str) -> Option<Value> {
+        self.scope.vars.borrow().get(name).cloned()
+    }
+
+    // the names bound in this scope itself (not its parents), sorted by name
+    pub fn bindings(&self) -> Vec<(String, Value)> {
+        let mut out: Vec<_> = self.scope.vars.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+        out.sort_by(|a, b| a.0.cmp(&b.0));
+        out
+    }
+}
@@ -1,149 +1,844 @@
-use crate::ast::{Stmt, Expr, Literal};
-use crate::environment::{Environment, Value, Function};
+use crate::ast::{Expr, ExprKind, Imported, Stmt, StmtKind, Type};
+use crate::builtins;
+use crate::diagnostic::{report, Diagnostic, Source};
+use crate::environment::{AssignError, Environment, Value, Function};
+use crate::module::{analyze, ImportError, Module, ModuleRegistry, Modules};
+use crate::span::Span;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
 use std::rc::Rc;
 
-pub fn interpret(program: Vec<Stmt>, env: &Environment) {
-    for s in program {
-        match s {
-            Stmt::ImportStmt(e) => {
-                if let Expr::Import { name, alias, module } = e {
-                    // For prototype: simulate import by registering a dummy function or builtin
-                    // e.g., "http_request" provides "request" which returns string "ok"
-                    if module == "http_request" {
-                        env.set(&alias.unwrap_or(name.clone()), Value::Func(Function {
-                            params: vec!["url".into()],
-                            body: vec![],
-                            types: vec![],
-                        }));
-                    } else {
-                        // unknown module: store Null
-                        env.set(&alias.unwrap_or(name.clone()), Value::Null);
-                    }
-                }
-            }
-            Stmt::FunctionDef(e) => {
-                if let Expr::Function { params, types, body } = e {
-                    // store anonymous function under special name? For now require user to assign externally.
-                    // For prototype let's register with a generated name or allow retrieving by index.
-                    // Simpler: store function under name "__anonN" not ideal but enough for demonstration.
-                    let fn_name = format!("__fn_{}", rand::random::<u32>());
-                    env.set(&fn_name, Value::Func(Function { params, body, types }));
-                    // print registration
-                    println!("Registered function {}", fn_name);
-                }
-            }
-            Stmt::Expr(expr) => {
-                eval_expr(expr, env);
-            }
-            _ => {}
-        }
-    }
-}
-
-fn eval_expr(expr: Expr, env: &Environment) -> Value {
-    match expr {
-        Expr::Lit(l) => Value::from(l),
-        Expr::Var(name) => {
-            env.get(&name).unwrap_or(Value::Null)
-        }
-        Expr::Binary { left, op, right } => {
-            let l = eval_expr(*left, env);
-            let r = eval_expr(*right, env);
-            match op.as_str() {
-                "plus" => {
-                    match (l, r) {
-                        (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
-                        (Value::Str(a), Value::Num(b)) => {
-                            Value::Str(format!("{}{}", a, b))
+// runtime error codes
+pub const E_TYPE: &str = "E0301";
+pub const E_UNDEFINED: &str = "E0302";
+pub const E_ARITY: &str = "E0303";
+pub const E_CONSTANT: &str = "E0304";
+pub const E_DIV_ZERO: &str = "E0305";
+pub const E_FLOW: &str = "E0306";
+pub const E_ARG_TYPE: &str = "E0307";
+pub const E_INDEX: &str = "E0308";
+pub const E_FIELD: &str = "E0309";
+pub const E_IMPORT: &str = "E0310";
+pub const E_IO: &str = "E0311";
+pub const E_DEPTH: &str = "E0312";
+
+// how many zeet function calls may be running at once; deeper recursion is almost
+// always a missing base case, and would otherwise overflow the rust stack
+pub const MAX_CALL_DEPTH: usize = 5_000;
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+    pub note: Option<String>,
+    // the code `span` points into, when that is not the program being run
+    pub source: Option<Rc<Source>>,
+}
+
+impl RuntimeError {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        RuntimeError { code, message: message.into(), span: None, note: None, source: None }
+    }
+
+    // attach a location unless a more precise one is already known
+    pub fn at(mut self, span: Span) -> Self {
+        self.span.get_or_insert(span);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.note = Some(note.into());
+        self
+    }
+
+    // the span was found in `source`, unless the error already knows where it is from
+    pub fn within(mut self, source: Option<&Rc<Source>>) -> Self {
+        if self.span.is_some() && self.source.is_none() {
+            self.source = source.cloned();
+        }
+        self
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let mut d = Diagnostic::error(self.code, self.message.clone(), self.span.unwrap_or_default());
+        if let Some(note) = &self.note {
+            d = d.with_note(note.clone());
+        }
+        d
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.span {
+            Some(span) => write!(f, "runtime error at {}:{}: {}", span.line, span.col, self.message),
+            None => write!(f, "runtime error: {}", self.message),
+        }
+    }
+}
+
+// how a statement finished: normally, by asking the enclosing loop to stop/skip,
+// or by returning a value from the enclosing function
+#[derive(Debug, Clone)]
+pub enum ControlFlow {
+    Normal,
+    Stop,
+    Skip,
+    Return(Value),
+}
+
+// tests a value for `x is <name>`; the plain types are predicates too
+pub type Predicate = Rc<dyn Fn(&Value) -> bool>;
+
+// interpreter state that outlives a single program: the named predicates
+// available to `is` and parameter annotations, the native modules, and the
+// module files loaded so far
+pub struct Interp {
+    predicates: HashMap<String, Predicate>,
+    natives: ModuleRegistry,
+    modules: Modules,
+    // the code running right now; functions remember it for their error messages
+    current: Option<Rc<Source>>,
+    // the zeet function calls running right now
+    depth: usize,
+}
+
+impl Default for Interp {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Interp {
+    pub fn new() -> Self {
+        let mut interp = Interp { predicates: HashMap::new(), natives: ModuleRegistry::default(), modules: Modules::new(), current: None, depth: 0 };
+        interp.register_predicate("any", |_| true);
+        interp.register_predicate("string", |v| matches!(v, Value::Str(_)));
+        interp.register_predicate("number", |v| matches!(v, Value::Num(_)));
+        interp.register_predicate("bool", |v| matches!(v, Value::Bool(_)));
+        interp.register_predicate("null", |v| matches!(v, Value::Null));
+        interp.register_predicate("record", |v| matches!(v, Value::Record(_)));
+        interp.register_predicate("function", |v| matches!(v, Value::Func(_) | Value::Builtin(_)));
+        interp.register_predicate("realNumber", |v| matches!(v, Value::Num(n) if n.is_finite()));
+        interp.register_predicate("integer", |v| matches!(v, Value::Num(n) if n.is_finite() && n.fract() == 0.0));
+        interp.register_predicate("empty", |v| match v {
+            Value::Null => true,
+            Value::Str(s) => s.is_empty(),
+            Value::Array(items) => items.borrow().is_empty(),
+            Value::Record(fields) => fields.borrow().is_empty(),
+            _ => false,
+        });
+        interp
+    }
+
+    // make `import ... from <name>` find a module written in rust, before looking for files
+    pub fn register_module(&mut self, name: &str, define: impl FnOnce(&Environment)) {
+        self.natives.register(name, define);
+    }
+
+    // the program about to be run, which imports are looked up relative to
+    pub fn set_source(&mut self, src: Rc<Source>) {
+        self.modules.restart(&src);
+        self.current = Some(src);
+    }
+
+    // make `x is <name>` available to programs, replacing any predicate of that name
+    pub fn register_predicate(&mut self, name: &str, predicate: impl Fn(&Value) -> bool + 'static) {
+        self.predicates.insert(name.to_string(), Rc::new(predicate));
+    }
+
+    // does the value fit a type or predicate, as in `x is integer Array`?
+    fn satisfies(&self, ty: &Type, v: &Value) -> Result<bool, RuntimeError> {
+        self.satisfies_in(ty, v, &mut Vec::new())
+    }
+
+    // `checking` holds the arrays being checked around this value, with their element
+    // types; an array that contains itself fits wherever its first check has not failed
+    fn satisfies_in(&self, ty: &Type, v: &Value, checking: &mut Vec<(*const Type, *const ())>) -> Result<bool, RuntimeError> {
+        let ok = match ty {
+            Type::Named(name) => match self.predicates.get(name) {
+                Some(predicate) => predicate(v),
+                None => {
+                    return Err(RuntimeError::new(E_UNDEFINED, format!("unknown type or predicate `{}`", name))
+                        .with_note("the host program has to register a predicate before `is` or an annotation can use it"));
+                }
+            },
+            Type::Array(elem) => match v {
+                Value::Array(items) => {
+                    let pair = (&**elem as *const Type, Rc::as_ptr(items) as *const ());
+                    if checking.contains(&pair) {
+                        return Ok(true);
+                    }
+                    checking.push(pair);
+                    let mut ok = true;
+                    for item in items.borrow().iter() {
+                        if !self.satisfies_in(elem, item, checking)? {
+                            ok = false;
+                            break;
                         }
-                        (Value::Str(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
-                        _ => Value::Null,
                     }
+                    checking.pop();
+                    ok
+                }
+                _ => false,
+            },
+            Type::Optional(inner) => matches!(v, Value::Null) || self.satisfies_in(inner, v, checking)?,
+            Type::Union(options) => {
+                for t in options {
+                    if self.satisfies_in(t, v, checking)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        };
+        Ok(ok)
+    }
+
+    pub fn interpret(&mut self, program: Vec<Stmt>, env: &Environment) -> Result<(), RuntimeError> {
+        // bind every named top-level function before running anything so that
+        // functions can call each other regardless of definition order
+        let mut rest = Vec::with_capacity(program.len());
+        for s in program {
+            // which names a module exports is worked out by whoever loads it
+            let s = match s.kind {
+                StmtKind::Export(inner) => *inner,
+                kind => Stmt::new(kind, s.span),
+            };
+            match s.kind {
+                StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(name), params, types, body }, .. }) => {
+                    let source = self.current.clone();
+                    let func = Value::Func(Rc::new(Function { params, body, types, closure: env.clone(), source }));
+                    declare(env, &name, func, false).map_err(|e| e.at(s.span))?;
+                }
+                _ => rest.push(s),
+            }
+        }
+
+        for s in rest {
+            match s.kind {
+                StmtKind::Import(Expr { kind: ExprKind::Import { what, module }, .. }) => {
+                    self.import(what, &module, env).map_err(|e| e.at(s.span))?;
+                }
+                _ => {
+                    let flow = self.exec_stmt(&s, env)?;
+                    check_flow(&flow).map_err(|e| e.at(s.span))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // like `interpret`, but a bare expression at the very end is evaluated for its
+    // value instead of being thrown away, so `zeet eval` has something to show
+    pub fn interpret_value(&mut self, mut program: Vec<Stmt>, env: &Environment) -> Result<Option<Value>, RuntimeError> {
+        let last = match program.last().map(|s| &s.kind) {
+            Some(StmtKind::Expr(e)) if !matches!(e.kind, ExprKind::If { .. } | ExprKind::Return(_)) => program.pop(),
+            _ => None,
+        };
+        self.interpret(program, env)?;
+        match last {
+            Some(Stmt { kind: StmtKind::Expr(e), .. }) => self.eval_expr(&e, env).map(Some),
+            _ => Ok(None),
+        }
+    }
+
+    fn import(&mut self, what: Imported, module: &str, env: &Environment) -> Result<(), RuntimeError> {
+        let loaded = match self.natives.get(module) {
+            Some(native) => native,
+            None => self.load_module(module)?,
+        };
+
+        match what {
+            Imported::Names(names) => {
+                for (name, alias) in names {
+                    let value = loaded.export(&name).map_err(|e| match e {
+                        ImportError::Missing => RuntimeError::new(E_IMPORT, format!("module `{}` has no `{}`", module, name)),
+                        ImportError::Private => {
+                            let why = if name.starts_with('_') {
+                                "names starting with `_` are private to their module".to_string()
+                            } else {
+                                let public: Vec<String> = loaded.public().into_iter().map(|(n, _)| n).collect();
+                                format!("`{}` exports only {}", module, public.join(", "))
+                            };
+                            RuntimeError::new(E_IMPORT, format!("`{}` is private to module `{}`", name, module)).with_note(why)
+                        }
+                    })?;
+                    declare(env, alias.as_ref().unwrap_or(&name), value, false)?;
+                }
+            }
+            Imported::All => {
+                for (name, value) in loaded.public() {
+                    declare(env, &name, value, false)?;
                 }
-                "and" => {
-                    match (l, r) {
-                        (Value::Bool(a), Value::Bool(b)) => Value::Bool(a && b),
-                        _ => Value::Null,
+            }
+            Imported::Module(alias) => {
+                let fields = loaded.public().into_iter().collect();
+                declare(env, alias.as_deref().unwrap_or(module), Value::record(fields), false)?;
+            }
+        }
+        Ok(())
+    }
+
+    // a module, run first if this is its first import. it gets its own builtins and
+    // sees nothing of the importing program
+    fn load_module(&mut self, name: &str) -> Result<Rc<Module>, RuntimeError> {
+        let importer = self.current.as_ref().and_then(|src| src.path.clone());
+        let path = self.modules.resolve(name, importer.as_deref()).map_err(|tried| {
+            let tried: Vec<String> = tried.iter().map(|p| p.display().to_string()).collect();
+            RuntimeError::new(E_IMPORT, format!("cannot find module `{}`", name))
+                .with_note(format!("looked for {}; set ZEET_PATH to search other directories", tried.join(", ")))
+        })?;
+        let key = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if let Some(module) = self.modules.get(&key) {
+            return Ok(module);
+        }
+
+        let code = fs::read_to_string(&path)
+            .map_err(|e| RuntimeError::new(E_IMPORT, format!("cannot read module `{}` ({}): {}", name, path.display(), e)))?;
+        let src = Rc::new(Source { name: path.display().to_string(), code, path: Some(path) });
+        self.modules.enter(key.clone(), src.name.clone())
+            .map_err(|chain| RuntimeError::new(E_IMPORT, format!("import cycle: {}", chain)))?;
+        let result = self.run_module(name, &src);
+        self.modules.leave();
+
+        let module = Rc::new(result?);
+        self.modules.insert(key, module.clone());
+        Ok(module)
+    }
+
+    fn run_module(&mut self, name: &str, src: &Rc<Source>) -> Result<Module, RuntimeError> {
+        let (prog, diags) = analyze(src);
+        if report(&diags, src) {
+            return Err(RuntimeError::new(E_IMPORT, format!("module `{}` has errors", name))
+                .with_note("they are reported above"));
+        }
+        let globals = Environment::new();
+        builtins::install(&globals);
+        let module = Module::new(globals.child(), &prog);
+
+        let outer = self.current.replace(src.clone());
+        let result = self.interpret(prog, &module.env);
+        self.current = outer;
+        result.map_err(|e| e.within(Some(src)))?;
+        Ok(module)
+    }
+
+    // run statements in order until one of them breaks the normal flow
+    fn exec_block(&mut self, stmts: &[Stmt], env: &Environment) -> Result<ControlFlow, RuntimeError> {
+        for st in stmts {
+            let flow = self.exec_stmt(st, env)?;
+            if !matches!(flow, ControlFlow::Normal) {
+                return Ok(flow);
+            }
+        }
+        Ok(ControlFlow::Normal)
+    }
+
+    // run one loop iteration in its own scope. `skip` just ends the iteration, so
+    // anything but Normal (`stop`, or a `ret` to pass on) ends the whole loop
+    fn exec_iteration(&mut self, body: &[Stmt], scope: &Environment) -> Result<ControlFlow, RuntimeError> {
+        match self.exec_block(body, scope)? {
+            ControlFlow::Skip => Ok(ControlFlow::Normal),
+            flow => Ok(flow),
+        }
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt, env: &Environment) -> Result<ControlFlow, RuntimeError> {
+        match &stmt.kind {
+            StmtKind::Expr(Expr { kind: ExprKind::If { cond, then_body, else_body }, .. }) => {
+                if self.condition(cond, env)? {
+                    return self.exec_block(then_body, env);
+                } else if let Some(else_block) = else_body {
+                    return self.exec_block(else_block, env);
+                }
+            }
+            StmtKind::Expr(Expr { kind: ExprKind::Return(value), .. }) => {
+                return Ok(ControlFlow::Return(self.eval_expr(value, env)?));
+            }
+            StmtKind::Expr(expr) => {
+                self.eval_expr(expr, env)?;
+            }
+            StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(name), params, types, body }, .. }) => {
+                declare(env, name, make_function(params, types, body, env, &self.current), false).map_err(|e| e.at(stmt.span))?;
+            }
+            // an anonymous function statement has no name to be called by
+            StmtKind::FunctionDef(_) => {}
+            StmtKind::Export(inner) => return self.exec_stmt(inner, env),
+            StmtKind::Let { name, value, constant } => {
+                let v = self.eval_expr(value, env)?;
+                declare(env, name, v, *constant).map_err(|e| e.at(stmt.span))?;
+            }
+            StmtKind::While { cond, body } => {
+                while self.condition(cond, env)? {
+                    match self.exec_iteration(body, &env.child())? {
+                        ControlFlow::Normal => {}
+                        ControlFlow::Return(v) => return Ok(ControlFlow::Return(v)),
+                        _ => break,
                     }
                 }
-                "same" => {
-                    // check string equality or number equality
-                    match (l, r) {
-                        (Value::Str(a), Value::Str(b)) => Value::Bool(a == b),
-                        (Value::Num(a), Value::Num(b)) => Value::Bool((a - b).abs() < 1e-9),
-                        _ => Value::Bool(false),
+            }
+            StmtKind::Repeat { count, body } => {
+                let n = match self.eval_expr(count, env)? {
+                    Value::Num(n) if n >= 0.0 && n.fract() == 0.0 => n as usize,
+                    other => {
+                        let msg = format!("`repeat` needs a whole non-negative number, got {}", describe(&other));
+                        return Err(RuntimeError::new(E_TYPE, msg).at(count.span));
+                    }
+                };
+                for _ in 0..n {
+                    match self.exec_iteration(body, &env.child())? {
+                        ControlFlow::Normal => {}
+                        ControlFlow::Return(v) => return Ok(ControlFlow::Return(v)),
+                        _ => break,
                     }
                 }
-                "not_equal" => {
-                    match (l, r) {
-                        (Value::Num(a), Value::Num(b)) => Value::Bool((a - b).abs() > 1e-9),
-                        (Value::Str(a), Value::Str(b)) => Value::Bool(a != b),
-                        _ => Value::Bool(true),
+            }
+            StmtKind::Each { var, iter, body } => {
+                let items = match self.eval_expr(iter, env)? {
+                    // loop over a snapshot so the body may change the array
+                    Value::Array(items) => items.borrow().clone(),
+                    // a record is looped over by its field names, in order
+                    Value::Record(fields) => fields.borrow().keys().map(|k| Value::Str(k.clone())).collect(),
+                    Value::Str(s) => s.chars().map(|c| Value::Str(c.to_string())).collect(),
+                    other => return Err(RuntimeError::new(E_TYPE, format!("cannot loop over {}", describe(&other))).at(iter.span)),
+                };
+                for item in items {
+                    // the loop variable only exists inside the body
+                    let scope = env.child();
+                    scope.define(var, item);
+                    match self.exec_iteration(body, &scope)? {
+                        ControlFlow::Normal => {}
+                        ControlFlow::Return(v) => return Ok(ControlFlow::Return(v)),
+                        _ => break,
                     }
                 }
-                _ => Value::Null,
             }
+            StmtKind::Stop => return Ok(ControlFlow::Stop),
+            StmtKind::Skip => return Ok(ControlFlow::Skip),
+            // imports only appear at the top level, where `interpret` handles them
+            StmtKind::Import(_) => {}
         }
-        Expr::If { cond, then_body, else_body } => {
-            let c = eval_expr(*cond, env);
-            let take_then = match c {
-                Value::Bool(b) => b,
-                _ => false,
-            };
-            if take_then {
-                for st in then_body {
-                    if let Stmt::Expr(e) = st {
-                        let val = eval_expr(e, env);
-                        // return on Return
-                        if let Value::Null = val { } // ignore
+        Ok(ControlFlow::Normal)
+    }
+
+    // if/while conditions have to be real booleans
+    fn condition(&mut self, cond: &Expr, env: &Environment) -> Result<bool, RuntimeError> {
+        match self.eval_expr(cond, env)? {
+            Value::Bool(b) => Ok(b),
+            other => Err(RuntimeError::new(E_TYPE, format!("condition must be true or false, got {}", describe(&other)))
+                .at(cond.span)),
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr, env: &Environment) -> Result<Value, RuntimeError> {
+        let value = match &expr.kind {
+            ExprKind::Lit(l) => Value::from(l.clone()),
+            ExprKind::Var(name) => {
+                env.get(name).ok_or_else(|| {
+                    RuntimeError::new(E_UNDEFINED, format!("undefined variable `{}`", name)).at(expr.span)
+                })?
+            }
+            ExprKind::Binary { left, op, right } => {
+                let l = self.eval_expr(left, env)?;
+                // `and` / `or` only look at the right side when they have to
+                match (op.as_str(), &l) {
+                    ("and", Value::Bool(false)) => return Ok(Value::Bool(false)),
+                    ("or", Value::Bool(true)) => return Ok(Value::Bool(true)),
+                    _ => {}
+                }
+                let r = self.eval_expr(right, env)?;
+                eval_binary(op, l, r).map_err(|e| e.at(expr.span))?
+            }
+            ExprKind::Is { expr: value, ty } => {
+                let v = self.eval_expr(value, env)?;
+                Value::Bool(self.satisfies(ty, &v).map_err(|e| e.at(expr.span))?)
+            }
+            ExprKind::Unary { op, expr } => {
+                let v = self.eval_expr(expr, env)?;
+                match (op.as_str(), v) {
+                    ("negate", Value::Num(n)) => Value::Num(-n),
+                    ("not", Value::Bool(b)) => Value::Bool(!b),
+                    ("length", Value::Array(items)) => Value::Num(items.borrow().len() as f64),
+                    ("length", Value::Record(fields)) => Value::Num(fields.borrow().len() as f64),
+                    ("length", Value::Str(s)) => Value::Num(s.chars().count() as f64),
+                    (op, v) => {
+                        let wanted = match op {
+                            "not" => "true or false",
+                            "length" => "an array, a record or a string",
+                            _ => "a number",
+                        };
+                        let msg = format!("`{}` needs {}, got {}", op, wanted, describe(&v));
+                        return Err(RuntimeError::new(E_TYPE, msg).at(expr.span));
                     }
                 }
-            } else if let Some(else_block) = else_body {
-                for st in else_block {
-                    if let Stmt::Expr(e) = st {
-                        let val = eval_expr(e, env);
+            }
+            ExprKind::Run(boxed) => {
+                // `run` is a call statement: `run f(x)` calls f, a bare `run f` or
+                // `run module.f` calls it with no arguments
+                match &boxed.kind {
+                    ExprKind::Var(_) | ExprKind::Field { .. } => {
+                        let f = self.eval_expr(boxed, env)?;
+                        self.call_value(&callee_name(boxed), f, Vec::new()).map_err(|e| e.at(expr.span))?
                     }
+                    _ => self.eval_expr(boxed, env)?,
                 }
             }
-            Value::Null
-        }
-        Expr::Run(boxed) => {
-            // handle as a function call where callee is Var(name) or Call
-            match *boxed {
-                Expr::Call{ callee, args } => {
-                    // not implemented heavy: return Null
-                    Value::Null
+            ExprKind::Return(boxed) => self.eval_expr(boxed, env)?,
+            ExprKind::Assign { name, value } => {
+                let v = self.eval_expr(value, env)?;
+                if let Err(AssignError::Constant) = env.set(name, v.clone()) {
+                    return Err(RuntimeError::new(E_CONSTANT, format!("cannot assign to constant `{}`", name)).at(expr.span));
+                }
+                v
+            }
+            ExprKind::Call { callee, args } => {
+                let name = callee_name(callee);
+                let f = match &callee.kind {
+                    ExprKind::Var(n) => env.get(n).ok_or_else(|| {
+                        RuntimeError::new(E_UNDEFINED, format!("undefined function `{}`", n)).at(callee.span)
+                    })?,
+                    _ => self.eval_expr(callee, env)?,
+                };
+                let mut values = Vec::with_capacity(args.len());
+                for a in args {
+                    values.push(self.eval_expr(a, env)?);
+                }
+                self.call_value(&name, f, values).map_err(|e| e.at(expr.span))?
+            }
+            ExprKind::Array(items) => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    values.push(self.eval_expr(item, env)?);
                 }
-                Expr::Var(name) => {
-                    // call builtin 'add' or imported functions: for prototype, if name == "add" and args absent, return dummy
-                    if name == "add" {
-                        Value::Num(42.0)
-                    } else {
-                        env.get(&name).unwrap_or(Value::Null)
+                Value::array(values)
+            }
+            ExprKind::Index { target, index } => {
+                let t = self.eval_expr(target, env)?;
+                let i = self.eval_expr(index, env)?;
+                let value = match &t {
+                    Value::Array(items) => {
+                        let items = items.borrow();
+                        position(&i, items.len()).map(|i| items[i].clone())
+                    }
+                    Value::Str(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        position(&i, chars.len()).map(|i| Value::Str(chars[i].to_string()))
+                    }
+                    other => Err(RuntimeError::new(E_TYPE, format!("cannot index into {}", describe(other)))),
+                };
+                value.map_err(|e| e.at(expr.span))?
+            }
+            ExprKind::SetIndex { target, index, value } => {
+                let t = self.eval_expr(target, env)?;
+                let i = self.eval_expr(index, env)?;
+                let v = self.eval_expr(value, env)?;
+                match &t {
+                    Value::Array(items) => {
+                        let mut items = items.borrow_mut();
+                        let i = position(&i, items.len()).map_err(|e| e.at(index.span))?;
+                        items[i] = v.clone();
+                    }
+                    other => {
+                        let msg = format!("cannot assign to an element of {}", describe(other));
+                        return Err(RuntimeError::new(E_TYPE, msg).at(target.span));
                     }
                 }
-                _ => Value::Null,
+                v
+            }
+            ExprKind::Record(fields) => {
+                let mut values = BTreeMap::new();
+                for (key, value) in fields {
+                    values.insert(key.clone(), self.eval_expr(value, env)?);
+                }
+                Value::record(values)
+            }
+            ExprKind::Field { target, name } => match self.eval_expr(target, env)? {
+                Value::Record(fields) => {
+                    let fields = fields.borrow();
+                    match fields.get(name) {
+                        Some(v) => v.clone(),
+                        None => {
+                            let known: Vec<&str> = fields.keys().map(String::as_str).collect();
+                            return Err(RuntimeError::new(E_FIELD, format!("record has no field `{}`", name))
+                                .with_note(match known.len() {
+                                    0 => "the record is empty".to_string(),
+                                    _ => format!("its fields are {}", known.join(", ")),
+                                })
+                                .at(expr.span));
+                        }
+                    }
+                }
+                other => {
+                    let msg = format!("cannot read field `{}` of {}", name, describe(&other));
+                    return Err(RuntimeError::new(E_TYPE, msg).at(target.span));
+                }
+            },
+            // assigning to a field that does not exist yet adds it
+            ExprKind::SetField { target, name, value } => {
+                let t = self.eval_expr(target, env)?;
+                let v = self.eval_expr(value, env)?;
+                match &t {
+                    Value::Record(fields) => {
+                        fields.borrow_mut().insert(name.clone(), v.clone());
+                    }
+                    other => {
+                        let msg = format!("cannot set field `{}` of {}", name, describe(other));
+                        return Err(RuntimeError::new(E_TYPE, msg).at(target.span));
+                    }
+                }
+                v
+            }
+            // an anonymous `__fn (...) ... __` used as a value
+            ExprKind::Function { params, types, body, .. } => make_function(params, types, body, env, &self.current),
+            _ => Value::Null,
+        };
+        Ok(value)
+    }
+
+    // call a function value with already evaluated arguments
+    pub fn call_value(&mut self, name: &str, f: Value, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        match f {
+            Value::Func(func) => self.call_function(name, &func, args),
+            Value::Builtin(b) => {
+                if let Some(arity) = b.arity {
+                    if arity != args.len() {
+                        return Err(arity_error(name, arity, args.len()));
+                    }
+                }
+                (b.func)(self, args)
+            }
+            other => Err(RuntimeError::new(E_TYPE, format!("`{}` is not a function (found {})", name, describe(&other)))),
+        }
+    }
+
+    fn call_function(&mut self, name: &str, func: &Function, args: Vec<Value>) -> Result<Value, RuntimeError> {
+        if func.params.len() != args.len() {
+            return Err(arity_error(name, func.params.len(), args.len()));
+        }
+        for (param, ty) in &func.types {
+            let i = func.params.iter().position(|p| p == param).unwrap_or_default();
+            if !self.satisfies(ty, &args[i])? {
+                let msg = format!("argument `{}` of `{}` must be {}, got {}", param, name, ty, describe(&args[i]));
+                return Err(RuntimeError::new(E_ARG_TYPE, msg)
+                    .with_note(format!("`{}` is annotated as `{} is {}`", name, param, ty)));
+            }
+        }
+        // parameters live in a fresh scope inside the one the function was defined in,
+        // so the body sees its surrounding variables and never the caller's
+        let scope = func.closure.child();
+        for (param, arg) in func.params.iter().zip(args) {
+            scope.define(param, arg);
+        }
+        if self.depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::new(E_DEPTH, format!("too many nested calls while calling `{}`", name))
+                .with_note(format!("at most {} calls can run at once; does a recursive function miss the case where it stops?", MAX_CALL_DEPTH)));
+        }
+        // the body may come from another file than the caller
+        let outer = std::mem::replace(&mut self.current, func.source.clone());
+        self.depth += 1;
+        let result = self.exec_body(&func.body, &scope);
+        self.depth -= 1;
+        self.current = outer;
+        result.map_err(|e| e.within(func.source.as_ref()))
+    }
+
+    fn exec_body(&mut self, body: &[Stmt], scope: &Environment) -> Result<Value, RuntimeError> {
+        for st in body {
+            match self.exec_stmt(st, scope)? {
+                ControlFlow::Return(v) => return Ok(v),
+                flow => check_flow(&flow).map_err(|e| e.at(st.span))?,
+            }
+        }
+        Ok(Value::Null)
+    }
+}
+
+// what a function is called in messages: `f`, `utils.shout`, or `<expression>` when
+// it comes from anything other than names and fields
+fn callee_name(callee: &Expr) -> String {
+    fn path(e: &Expr) -> Option<String> {
+        match &e.kind {
+            ExprKind::Var(name) => Some(name.clone()),
+            ExprKind::Field { target, name } => path(target).map(|t| format!("{}.{}", t, name)),
+            _ => None,
+        }
+    }
+    path(callee).unwrap_or_else(|| "<expression>".to_string())
+}
+
+// bind a name the program introduces (`let`, `const`, a named `__fn` or an import),
+// which must not replace a constant of the same scope
+fn declare(env: &Environment, name: &str, value: Value, constant: bool) -> Result<(), RuntimeError> {
+    env.declare(name, value, constant)
+        .map_err(|_| RuntimeError::new(E_CONSTANT, format!("cannot redeclare constant `{}`", name)))
+}
+
+fn make_function(params: &[String], types: &[(String, Type)], body: &[Stmt], env: &Environment, source: &Option<Rc<Source>>) -> Value {
+    Value::Func(Rc::new(Function {
+        params: params.to_vec(),
+        body: body.to_vec(),
+        types: types.to_vec(),
+        closure: env.clone(),
+        source: source.clone(),
+    }))
+}
+
+// `stop` / `skip` that escaped every loop, or `ret` that escaped every function
+fn check_flow(flow: &ControlFlow) -> Result<(), RuntimeError> {
+    match flow {
+        ControlFlow::Normal => Ok(()),
+        ControlFlow::Stop => Err(RuntimeError::new(E_FLOW, "`stop` used outside of a loop")),
+        ControlFlow::Skip => Err(RuntimeError::new(E_FLOW, "`skip` used outside of a loop")),
+        ControlFlow::Return(_) => Err(RuntimeError::new(E_FLOW, "`ret` used outside of a function")
+            .with_note("only a function body can return a value")),
+    }
+}
+
+// a value as it appears in error messages, e.g. `number 3` or `string "hi"`
+pub fn describe(v: &Value) -> String {
+    match v {
+        Value::Null => "null".to_string(),
+        Value::Str(s) => format!("string \"{}\"", s),
+        Value::Func(_) | Value::Builtin(_) => v.type_name().to_string(),
+        _ => format!("{} {}", v.type_name(), v),
+    }
+}
+
+fn eval_binary(op: &str, l: Value, r: Value) -> Result<Value, RuntimeError> {
+    let value = match op {
+        "plus" => {
+            match (l, r) {
+                (Value::Num(a), Value::Num(b)) => Value::Num(a + b),
+                (Value::Str(a), Value::Num(b)) => {
+                    Value::Str(format!("{}{}", a, b))
+                }
+                (Value::Str(a), Value::Str(b)) => Value::Str(format!("{}{}", a, b)),
+                // joining arrays makes a new array; neither side changes
+                (Value::Array(a), Value::Array(b)) => {
+                    let mut items = a.borrow().clone();
+                    items.extend(b.borrow().iter().cloned());
+                    Value::array(items)
+                }
+                (l, r) => {
+                    return Err(RuntimeError::new(E_TYPE, format!("cannot add {} and {}", describe(&l), describe(&r)))
+                        .with_note("`plus` adds two numbers, appends to a string or joins two arrays"));
+                }
+            }
+        }
+        "minus" | "times" | "divided_by" | "mod" => {
+            match (l, r) {
+                (Value::Num(a), Value::Num(b)) => match op {
+                    "minus" => Value::Num(a - b),
+                    "times" => Value::Num(a * b),
+                    _ if b == 0.0 => return Err(RuntimeError::new(E_DIV_ZERO, "division by zero")),
+                    "divided_by" => Value::Num(a / b),
+                    _ => Value::Num(a % b),
+                },
+                (l, r) => return Err(operand_error(op, "two numbers", &l, &r)),
             }
         }
-        Expr::Return(boxed) => {
-            // return expression value: here we just evaluate and print result
-            let v = eval_expr(*boxed, env);
-            println!("Return => {:?}", v);
-            v
+        "and" | "or" => {
+            match (l, r) {
+                (Value::Bool(a), Value::Bool(b)) => Value::Bool(if op == "and" { a && b } else { a || b }),
+                (l, r) => return Err(operand_error(op, "true or false on both sides", &l, &r)),
+            }
+        }
+        "same" => Value::Bool(same(&l, &r)),
+        "not_equal" => {
+            match eval_binary("same", l, r)? {
+                Value::Bool(b) => Value::Bool(!b),
+                other => other,
+            }
         }
-        Expr::Call { callee, args } => {
-            // support simple form: callee is Var(name)
-            if let Expr::Var(name) = *callee {
-                // find in env
-                if let Some(Value::Func(f)) = env.get(&name) {
-                    // for prototype: do not execute body, just return Null or dummy
-                    return Value::Null;
-                } else {
-                    return Value::Null;
+        "greater_than" | "less_than" | "at_least" | "at_most" => {
+            let ord = match (&l, &r) {
+                (Value::Num(a), Value::Num(b)) => a.partial_cmp(b),
+                (Value::Str(a), Value::Str(b)) => Some(a.cmp(b)),
+                _ => None,
+            };
+            match ord {
+                Some(o) => Value::Bool(match op {
+                    "greater_than" => o.is_gt(),
+                    "less_than" => o.is_lt(),
+                    "at_least" => o.is_ge(),
+                    _ => o.is_le(),
+                }),
+                None => {
+                    return Err(RuntimeError::new(E_TYPE, format!("cannot compare {} with {}", describe(&l), describe(&r)))
+                        .with_note("only two numbers or two strings can be ordered"));
                 }
             }
-            Value::Null
         }
-        _ => Value::Null,
+        _ => return Err(RuntimeError::new(E_TYPE, format!("unsupported operator `{}`", op))),
+    };
+    Ok(value)
+}
+
+// structural equality: arrays and records are the same when their contents are
+fn same(l: &Value, r: &Value) -> bool {
+    same_in(l, r, &mut Vec::new())
+}
+
+// `compared` holds the pairs of arrays or records being compared around these values.
+// meeting a pair again means the values contain themselves, and nothing found so far
+// tells them apart
+fn same_in(l: &Value, r: &Value, compared: &mut Vec<(*const (), *const ())>) -> bool {
+    let pair = match (l, r) {
+        (Value::Array(a), Value::Array(b)) => Some((Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ())),
+        (Value::Record(a), Value::Record(b)) => Some((Rc::as_ptr(a) as *const (), Rc::as_ptr(b) as *const ())),
+        _ => None,
+    };
+    if let Some(pair) = pair {
+        if pair.0 == pair.1 || compared.contains(&pair) {
+            return true;
+        }
+        compared.push(pair);
     }
-}
\ No newline at end of file
+    let result = match (l, r) {
+        (Value::Str(a), Value::Str(b)) => a == b,
+        (Value::Num(a), Value::Num(b)) => (a - b).abs() < 1e-9,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Null, Value::Null) => true,
+        (Value::Array(a), Value::Array(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| same_in(x, y, compared))
+        }
+        (Value::Record(a), Value::Record(b)) => {
+            let (a, b) = (a.borrow(), b.borrow());
+            a.len() == b.len() && a.iter().zip(b.iter()).all(|((ka, va), (kb, vb))| ka == kb && same_in(va, vb, compared))
+        }
+        _ => false,
+    };
+    if pair.is_some() {
+        compared.pop();
+    }
+    result
+}
+
+// check an index value against a length, giving back a usable position
+fn position(index: &Value, len: usize) -> Result<usize, RuntimeError> {
+    match index {
+        Value::Num(n) if n.fract() == 0.0 && *n >= 0.0 && (*n as usize) < len => Ok(*n as usize),
+        Value::Num(n) => Err(RuntimeError::new(E_INDEX, format!("index {} is out of range", n))
+            .with_note(match len {
+                0 => "there are no elements".to_string(),
+                _ => format!("valid positions are 0 to {} here", len - 1),
+            })),
+        other => Err(RuntimeError::new(E_TYPE, format!("an index must be a number, got {}", describe(other)))),
+    }
+}
+
+fn operand_error(op: &str, wanted: &str, l: &Value, r: &Value) -> RuntimeError {
+    let op = op.replace('_', " ");
+    RuntimeError::new(E_TYPE, format!("`{}` needs {}, got {} and {}", op, wanted, describe(l), describe(r)))
+}
+
+pub fn arity_error(name: &str, expected: usize, got: usize) -> RuntimeError {
+    RuntimeError::new(E_ARITY, format!(
+        "`{}` expects {} argument{}, got {}",
+        name, expected, if expected == 1 { "" } else { "s" }, got
+    ))
+}
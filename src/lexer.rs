@@ -1,30 +1,27 @@
-#[derive(Debug, Clone, PartialEq)]
-pub enum Token {
-    Import,
-    From,
-    Arrow,         // ->
-    Assign,        // =
-    FnStart,       // __fn
-    BlockEnd,      // __
-    Identifier(String),
-    StringLiteral(String),
-    NumberLiteral(f64),
-    Keyword(String),   // if, then, otherwise, run, ret, etc.
-    Operator(String),  // plus, minus, equal, not equal
-    LParen, RParen,
-    Colon, Comma,
-    Lt, Gt,          // for <types>
-    EOF,
-}
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+use crate::token::{SpannedToken, Token};
 
 pub struct Lexer {
     input: Vec<char>,
     pos: usize,
+    // byte offset of every char index (plus one past the end), for spans
+    offsets: Vec<usize>,
+    line: usize,
+    // char index where the current line begins
+    line_start: usize,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
-        Self { input: input.chars().collect(), pos: 0 }
+        let mut offsets: Vec<usize> = input.char_indices().map(|(i, _)| i).collect();
+        offsets.push(input.len());
+        Self { input: input.chars().collect(), pos: 0, offsets, line: 1, line_start: 0 }
+    }
+
+    // span from char index `start` (on the current line) up to the current position
+    fn span_from(&self, start: usize, line: usize, col: usize) -> Span {
+        Span::new(self.offsets[start], self.offsets[self.pos], line, col)
     }
 
     fn peek(&self) -> Option<char> {
@@ -35,6 +32,10 @@ impl Lexer {
         if self.pos < self.input.len() {
             let c = self.input[self.pos];
             self.pos += 1;
+            if c == '\n' {
+                self.line += 1;
+                self.line_start = self.pos;
+            }
             Some(c)
         } else {
             None
@@ -57,42 +58,115 @@ impl Lexer {
         result
     }
 
-    pub fn tokenize(&mut self) -> Vec<Token> {
+    // after a '-' we may be looking at the "- then," form used by if blocks
+    // or the "- do," form used by loops
+    fn try_block_marker(&mut self) -> Option<Token> {
+        let start = self.pos;
+        self.consume_while(|ch| ch == ' ' || ch == '\t');
+        let word = self.consume_while(|ch| ch.is_alphanumeric() || ch == '_');
+        let tok = match word.as_str() {
+            "then" => Token::Then,
+            "do" => Token::Do,
+            _ => {
+                self.pos = start;
+                return None;
+            }
+        };
+        if self.peek() == Some(',') { self.advance(); }
+        Some(tok)
+    }
+
+    // the token stream (always ending in Eof) plus any problems found on the way;
+    // malformed input is reported and skipped so that lexing can carry on
+    pub fn tokenize(&mut self) -> (Vec<SpannedToken>, Vec<Diagnostic>) {
         let mut tokens = vec![];
+        let mut spans = vec![];
+        let mut diagnostics = vec![];
 
         while let Some(c) = self.peek() {
+            let (start, line, col) = (self.pos, self.line, self.pos - self.line_start + 1);
             match c {
                 ' ' | '\t' | '\n' | '\r' => { self.advance(); },
                 '(' => { tokens.push(Token::LParen); self.advance(); },
                 ')' => { tokens.push(Token::RParen); self.advance(); },
+                '[' => { tokens.push(Token::LBracket); self.advance(); },
+                ']' => { tokens.push(Token::RBracket); self.advance(); },
+                '{' => { tokens.push(Token::LBrace); self.advance(); },
+                '}' => { tokens.push(Token::RBrace); self.advance(); },
+                '.' => { tokens.push(Token::Dot); self.advance(); },
                 ',' => { tokens.push(Token::Comma); self.advance(); },
                 ':' => { tokens.push(Token::Colon); self.advance(); },
-                '<' => { tokens.push(Token::Lt); self.advance(); },
-                '>' => { tokens.push(Token::Gt); self.advance(); },
+                '<' => {
+                    self.advance();
+                    if self.peek() == Some('=') { self.advance(); tokens.push(Token::LtEq); } else { tokens.push(Token::LAngle); }
+                }
+                '>' => {
+                    self.advance();
+                    if self.peek() == Some('=') { self.advance(); tokens.push(Token::GtEq); } else { tokens.push(Token::RAngle); }
+                }
+                '!' => {
+                    self.advance();
+                    if self.peek() == Some('=') {
+                        self.advance();
+                        tokens.push(Token::NotEq);
+                    } else {
+                        diagnostics.push(
+                            Diagnostic::error("E0103", "unexpected character `!`", self.span_from(start, line, col))
+                                .with_help("use `!=` (or `not equal`) to compare, and `not` to negate"),
+                        );
+                    }
+                }
+                '+' => { tokens.push(Token::Plus); self.advance(); },
+                '*' => { tokens.push(Token::Star); self.advance(); },
+                '/' => { tokens.push(Token::Slash); self.advance(); },
                 '"' => {
                     self.advance(); // skip quote
                     let s = self.consume_while(|ch| ch != '"');
-                    self.advance(); // skip ending quote
-                    tokens.push(Token::StringLiteral(s));
+                    if self.advance().is_none() {
+                        diagnostics.push(
+                            Diagnostic::error("E0102", "unterminated string literal", self.span_from(start, line, col))
+                                .with_label("string starts here")
+                                .with_help("add a closing `\"`"),
+                        );
+                    }
+                    tokens.push(Token::StringLit(s));
                 }
                 '0'..='9' => {
                     let num = self.consume_while(|ch| ch.is_ascii_digit() || ch == '.');
-                    tokens.push(Token::NumberLiteral(num.parse().unwrap()));
+                    match num.parse() {
+                        Ok(n) => tokens.push(Token::NumberLit(n)),
+                        Err(_) => {
+                            diagnostics.push(
+                                Diagnostic::error("E0101", format!("invalid number literal `{}`", num), self.span_from(start, line, col))
+                                    .with_label("not a number")
+                                    .with_note("a number has at most one decimal point, like `1.25`"),
+                            );
+                            // keep a placeholder so the parser sees a well-formed expression
+                            tokens.push(Token::NumberLit(0.0));
+                        }
+                    }
                 }
                 '-' => {
                     self.advance();
                     if self.peek() == Some('>') {
                         self.advance();
                         tokens.push(Token::Arrow);
+                    } else if let Some(marker) = self.try_block_marker() {
+                        tokens.push(marker);
+                    } else {
+                        tokens.push(Token::Minus);
                     }
                 }
-                '=' => { tokens.push(Token::Assign); self.advance(); },
+                '=' => {
+                    self.advance();
+                    if self.peek() == Some('=') { self.advance(); tokens.push(Token::EqEq); } else { tokens.push(Token::Equals); }
+                }
                 '_' => {
                     let word = self.consume_while(|ch| ch.is_alphanumeric() || ch == '_');
                     if word == "__fn" {
-                        tokens.push(Token::FnStart);
+                        tokens.push(Token::FnKw);
                     } else if word == "__" {
-                        tokens.push(Token::BlockEnd);
+                        tokens.push(Token::Underscore);
                     } else {
                         tokens.push(Token::Identifier(word));
                     }
@@ -102,16 +176,46 @@ impl Lexer {
                     match word.as_str() {
                         "import" => tokens.push(Token::Import),
                         "from" => tokens.push(Token::From),
-                        "if" | "then" | "otherwise" | "run" | "ret" => tokens.push(Token::Keyword(word)),
-                        "plus" | "minus" | "equal" | "not" => tokens.push(Token::Operator(word)),
+                        "export" => tokens.push(Token::Export),
+                        "if" => tokens.push(Token::If),
+                        "then" => tokens.push(Token::Then),
+                        "otherwise" => tokens.push(Token::Otherwise),
+                        "run" => tokens.push(Token::Run),
+                        "ret" => tokens.push(Token::Ret),
+                        "let" => tokens.push(Token::Let),
+                        "const" => tokens.push(Token::Const),
+                        "while" => tokens.push(Token::While),
+                        "each" => tokens.push(Token::Each),
+                        "in" => tokens.push(Token::In),
+                        "repeat" => tokens.push(Token::Repeat),
+                        "do" => tokens.push(Token::Do),
+                        "stop" => tokens.push(Token::Stop),
+                        "skip" => tokens.push(Token::Skip),
+                        "true" => tokens.push(Token::BoolLit(true)),
+                        "false" => tokens.push(Token::BoolLit(false)),
+                        "null" => tokens.push(Token::NullLit),
+                        // operator words (plus, and, same, is, not, ...) stay identifiers;
+                        // the parser decides what they mean from their position
                         _ => tokens.push(Token::Identifier(word)),
                     }
                 }
-                _ => { self.advance(); }
+                _ => {
+                    self.advance();
+                    diagnostics.push(
+                        Diagnostic::error("E0103", format!("unexpected character `{}`", c), self.span_from(start, line, col))
+                            .with_label("not part of any token"),
+                    );
+                }
+            }
+            // every token pushed by this iteration covers the text consumed by it
+            while spans.len() < tokens.len() {
+                spans.push(self.span_from(start, line, col));
             }
         }
 
-        tokens.push(Token::EOF);
-        tokens
+        tokens.push(Token::Eof);
+        spans.push(self.span_from(self.pos, self.line, self.pos - self.line_start + 1));
+        let tokens = tokens.into_iter().zip(spans).map(|(token, span)| SpannedToken { token, span }).collect();
+        (tokens, diagnostics)
     }
-}
\ No newline at end of file
+}
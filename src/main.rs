@@ -1,57 +1,220 @@
+mod span;
+mod diagnostic;
 mod token;
 mod lexer;
 mod ast;
 mod parser;
 mod environment;
 mod interpreter;
+mod checker;
+mod builtins;
+mod stdlib;
+mod module;
+mod repl;
 
+use crate::ast::Stmt;
+use crate::diagnostic::{report, Diagnostic, Source};
 use crate::lexer::Lexer;
 use crate::parser::Parser;
-use crate::environment::Environment;
-use crate::interpreter::interpret;
+use crate::environment::{Environment, Value};
+use crate::interpreter::{Interp, RuntimeError};
+use crate::module::analyze;
+use clap::{Parser as ClapParser, Subcommand};
 use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::rc::Rc;
 
-fn main() {
-    // Example: read file from first arg or run built-in example
-    let args: Vec<String> = std::env::args().collect();
-    let code = if args.len() > 1 {
-        fs::read_to_string(&args[1]).expect("cannot read file")
+// exit codes: 0 when all went well, 1 when the program has errors (found while
+// checking or while running it), 2 when zeet was used wrongly or the input could not be read
+const EXIT_PROGRAM: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+
+// the interpreter recurses on the rust stack, a few frames for every zeet call, so it
+// runs on a thread with room for `Interp`'s call depth limit even in debug builds.
+// only the pages actually used are ever allocated
+const STACK_SIZE: usize = 1 << 30;
+
+#[derive(ClapParser)]
+#[command(name = "zeet", version, about = "The zeet programming language")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check a program and run it
+    Run {
+        /// The program to run, or `-` to read it from stdin
+        file: String,
+        /// Arguments for the program, given to it as the `args` array
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Report problems in a program without running it
+    Check {
+        /// The program to check, or `-` to read it from stdin
+        file: String,
+    },
+    /// Print the tokens of a program
+    Tokens {
+        /// The program to read, or `-` to read it from stdin
+        file: String,
+    },
+    /// Print the syntax tree of a program
+    Ast {
+        /// The program to read, or `-` to read it from stdin
+        file: String,
+        /// Print the tree as JSON instead of Rust debug output
+        #[arg(long)]
+        json: bool,
+    },
+    /// Evaluate code given on the command line and print its value
+    Eval {
+        /// The code to evaluate, e.g. 'add(1, 2)'
+        #[arg(allow_hyphen_values = true)]
+        expr: String,
+    },
+    /// Start an interactive session
+    Repl,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let worker = std::thread::Builder::new().stack_size(STACK_SIZE).spawn(move || execute(cli));
+    let result = match worker.map(|w| w.join()) {
+        Ok(Ok(result)) => result,
+        // a panic has already printed its message
+        Ok(Err(_)) => Err(EXIT_PROGRAM),
+        Err(e) => {
+            eprintln!("error: cannot start the interpreter: {}", e);
+            Err(EXIT_USAGE)
+        }
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(code) => ExitCode::from(code),
+    }
+}
+
+fn execute(cli: Cli) -> Result<(), u8> {
+    match cli.command {
+        Command::Run { file, args } => read_source(&file).and_then(|src| run(src, args)),
+        Command::Check { file } => read_source(&file).and_then(|src| check(&src)),
+        Command::Tokens { file } => read_source(&file).and_then(|src| tokens(&src)),
+        Command::Ast { file, json } => read_source(&file).and_then(|src| ast(&src, json)),
+        Command::Eval { expr } => eval(Source { name: "<eval>".into(), code: expr, path: None }),
+        Command::Repl => repl::start(),
+    }
+}
+
+// `-` is stdin, anything else a path
+fn read_source(path: &str) -> Result<Source, u8> {
+    let (name, code, file) = if path == "-" {
+        let mut code = String::new();
+        let read = io::stdin().read_to_string(&mut code);
+        ("<stdin>".to_string(), read.map(|_| code), None)
     } else {
-        // sample program (from your example)
-        r#"
-import request from http_request
-import request -> req from http_request
-
-__fn = (a,b):<a is string, b is string Array>
-       if(a and b same) - then,
-         run add(a plus 3)
-       otherwise - ret false
-__
-        
-__fn = (a):<a is number>
-       if ( a is realNumber and a not equal 0 ) - then,
-          ret a
-        __
-__
-"#
-        .to_string()
+        (path.to_string(), fs::read_to_string(path), Some(PathBuf::from(path)))
     };
+    match code {
+        Ok(code) => Ok(Source { name, code, path: file }),
+        Err(e) => {
+            eprintln!("error: cannot read `{}`: {}", name, e);
+            Err(EXIT_USAGE)
+        }
+    }
+}
+
+fn run(src: Source, args: Vec<String>) -> Result<(), u8> {
+    let prog = compile(&src)?;
+    let env = Environment::new();
+    builtins::install(&env);
+    env.define("args", Value::array(args.into_iter().map(Value::Str).collect()));
+
+    let src = Rc::new(src);
+    let mut interp = Interp::new();
+    stdlib::install(&mut interp);
+    interp.set_source(src.clone());
+    if let Err(e) = interp.interpret(prog, &env) {
+        report_runtime(&e, &src);
+        return Err(EXIT_PROGRAM);
+    }
+    Ok(())
+}
 
-    let mut lx = Lexer::new(&code);
-    let toks = lx.tokenize();
-    // println!("TOKENS: {:?}", toks);
+fn check(src: &Source) -> Result<(), u8> {
+    let (_, diags) = analyze(src);
+    let failed = report(&diags, src);
+    let errors = diags.iter().filter(|d| d.is_error()).count();
+    let warnings = diags.len() - errors;
+    eprintln!("{}: {} error{}, {} warning{}", src.name, errors, plural(errors), warnings, plural(warnings));
+    if failed { Err(EXIT_PROGRAM) } else { Ok(()) }
+}
 
-    let mut p = Parser::new(toks);
-    let prog = p.parse_program();
-    // println!("AST: {:#?}", prog);
+// one token per line, with the line and column it starts at
+fn tokens(src: &Source) -> Result<(), u8> {
+    let (toks, diags) = Lexer::new(&src.code).tokenize();
+    for t in &toks {
+        println!("{:>4}:{:<4} {:?}", t.span.line, t.span.col, t.token);
+    }
+    if report(&diags, src) { Err(EXIT_PROGRAM) } else { Ok(()) }
+}
+
+// the tree is printed even when there are syntax errors, as far as it could be parsed
+fn ast(src: &Source, json: bool) -> Result<(), u8> {
+    let (toks, lex_diags) = Lexer::new(&src.code).tokenize();
+    let (prog, parse_diags) = Parser::new(toks).parse_program();
+    if json {
+        match serde_json::to_string_pretty(&prog) {
+            Ok(text) => println!("{}", text),
+            Err(e) => {
+                eprintln!("error: cannot write the tree as JSON: {}", e);
+                return Err(EXIT_PROGRAM);
+            }
+        }
+    } else {
+        println!("{:#?}", prog);
+    }
+    let diags: Vec<Diagnostic> = lex_diags.into_iter().chain(parse_diags).collect();
+    if report(&diags, src) { Err(EXIT_PROGRAM) } else { Ok(()) }
+}
 
+fn eval(src: Source) -> Result<(), u8> {
+    let prog = compile(&src)?;
     let env = Environment::new();
-    // add a builtin function 'add' to demonstrate run
-    env.set("add", Value::Func(crate::environment::Function {
-        params: vec!["x".into()],
-        body: vec![],
-        types: vec![],
-    }));
-
-    interpret(prog, &env);
-}
\ No newline at end of file
+    builtins::install(&env);
+
+    let src = Rc::new(src);
+    let mut interp = Interp::new();
+    stdlib::install(&mut interp);
+    interp.set_source(src.clone());
+    match interp.interpret_value(prog, &env) {
+        Ok(Some(Value::Null)) | Ok(None) => Ok(()),
+        Ok(Some(v)) => {
+            println!("{}", v);
+            Ok(())
+        }
+        Err(e) => {
+            report_runtime(&e, &src);
+            Err(EXIT_PROGRAM)
+        }
+    }
+}
+
+// the program, if it is fit to run; its diagnostics are reported either way
+fn compile(src: &Source) -> Result<Vec<Stmt>, u8> {
+    let (prog, diags) = analyze(src);
+    if report(&diags, src) { Err(EXIT_PROGRAM) } else { Ok(prog) }
+}
+
+// an error raised while running `src`, shown in the file it happened in
+fn report_runtime(e: &RuntimeError, src: &Source) {
+    report(&[e.to_diagnostic()], e.source.as_deref().unwrap_or(src));
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 { "" } else { "s" }
+}
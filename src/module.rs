@@ -0,0 +1,235 @@
+use crate::ast::{Expr, ExprKind, Stmt, StmtKind};
+use crate::checker::check_program;
+use crate::diagnostic::{Diagnostic, Source};
+use crate::environment::{Environment, Value};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const EXTENSION: &str = "zt";
+
+// lex, parse and type check a program
+pub fn analyze(src: &Source) -> (Vec<Stmt>, Vec<Diagnostic>) {
+    let (toks, lex_diags) = Lexer::new(&src.code).tokenize();
+    let (prog, parse_diags) = Parser::new(toks).parse_program();
+    let mut diags: Vec<Diagnostic> = lex_diags.into_iter().chain(parse_diags).collect();
+    // type checking a program with syntax errors would only report their fallout
+    if !diags.iter().any(Diagnostic::is_error) {
+        diags.extend(check_program(&prog));
+    }
+    (prog, diags)
+}
+
+// a module that has been run: the scope its top level ran in, and which of its names
+// other files may import. names starting with `_` are private, and once a module
+// exports anything, everything it does not export is private too
+pub struct Module {
+    pub env: Environment,
+    pub exported: Option<HashSet<String>>,
+}
+
+// why a name cannot be imported from a module
+pub enum ImportError {
+    Missing,
+    Private,
+}
+
+impl Module {
+    pub fn new(env: Environment, program: &[Stmt]) -> Self {
+        let mut exported = None;
+        for st in program {
+            if let StmtKind::Export(inner) = &st.kind {
+                if let Some(name) = defined_name(inner) {
+                    exported.get_or_insert_with(HashSet::new).insert(name.to_string());
+                }
+            }
+        }
+        Module { env, exported }
+    }
+
+    pub fn is_public(&self, name: &str) -> bool {
+        !name.starts_with('_') && self.exported.as_ref().is_none_or(|names| names.contains(name))
+    }
+
+    pub fn export(&self, name: &str) -> Result<Value, ImportError> {
+        let value = self.env.get_local(name).ok_or(ImportError::Missing)?;
+        if self.is_public(name) { Ok(value) } else { Err(ImportError::Private) }
+    }
+
+    // every name other files may import, with its value
+    pub fn public(&self) -> Vec<(String, Value)> {
+        self.env.bindings().into_iter().filter(|(name, _)| self.is_public(name)).collect()
+    }
+}
+
+// the name a `let`, `const` or named `__fn` binds
+fn defined_name(st: &Stmt) -> Option<&str> {
+    match &st.kind {
+        StmtKind::Let { name, .. } => Some(name),
+        StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(name), .. }, .. }) => Some(name),
+        _ => None,
+    }
+}
+
+// modules written in rust, which imports look at before any file. each one is a scope
+// holding the builtins (and other values) it exports
+#[derive(Default)]
+pub struct ModuleRegistry {
+    modules: HashMap<String, Rc<Module>>,
+}
+
+impl ModuleRegistry {
+    // add a module, or replace the one of that name; `define` fills in what it exports
+    pub fn register(&mut self, name: &str, define: impl FnOnce(&Environment)) {
+        let env = Environment::new();
+        define(&env);
+        self.modules.insert(name.to_string(), Rc::new(Module { env, exported: None }));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Rc<Module>> {
+        self.modules.get(name).cloned()
+    }
+}
+
+// where `import x from utils` finds `utils.zt`, and the modules loaded so far
+#[derive(Default)]
+pub struct Modules {
+    // directories from ZEET_PATH, searched after the importing file's own directory
+    search_path: Vec<PathBuf>,
+    // every module loaded so far, by canonical path
+    loaded: HashMap<PathBuf, Rc<Module>>,
+    // the files being loaded right now, outermost first, with the names they are
+    // reported under; importing one of them again is a cycle
+    loading: Vec<(PathBuf, String)>,
+}
+
+impl Modules {
+    pub fn new() -> Self {
+        let search_path = env::var_os("ZEET_PATH").map(|p| env::split_paths(&p).collect()).unwrap_or_default();
+        Modules { search_path, ..Modules::default() }
+    }
+
+    // the file a module name refers to, or every place that was tried
+    pub fn resolve(&self, name: &str, importer: Option<&Path>) -> Result<PathBuf, Vec<PathBuf>> {
+        let file = format!("{}.{}", name, EXTENSION);
+        // code that did not come from a file imports from the working directory
+        let dir = importer.and_then(Path::parent).unwrap_or(Path::new(""));
+        let candidates: Vec<PathBuf> =
+            std::iter::once(dir.join(&file)).chain(self.search_path.iter().map(|d| d.join(&file))).collect();
+        match candidates.iter().find(|p| p.is_file()) {
+            Some(path) => Ok(path.clone()),
+            None => Err(candidates),
+        }
+    }
+
+    pub fn get(&self, path: &Path) -> Option<Rc<Module>> {
+        self.loaded.get(path).cloned()
+    }
+
+    pub fn insert(&mut self, path: PathBuf, module: Rc<Module>) {
+        self.loaded.insert(path, module);
+    }
+
+    // start loading a file; the chain of imports that leads back to it if that is a cycle
+    pub fn enter(&mut self, path: PathBuf, name: String) -> Result<(), String> {
+        if let Some(i) = self.loading.iter().position(|(p, _)| *p == path) {
+            let chain: Vec<&str> = self.loading[i..].iter().map(|(_, n)| n.as_str()).chain([name.as_str()]).collect();
+            return Err(chain.join(" -> "));
+        }
+        self.loading.push((path, name));
+        Ok(())
+    }
+
+    pub fn leave(&mut self) {
+        self.loading.pop();
+    }
+
+    // forget what was being loaded and start from a new top-level file
+    pub fn restart(&mut self, src: &Source) {
+        self.loading.clear();
+        if let Some(path) = src.path.as_ref().and_then(|p| p.canonicalize().ok()) {
+            self.loading.push((path, src.name.clone()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportError, Module, Modules};
+    use crate::environment::{Environment, Value};
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    // the module of `code`, with each of `names` defined as if it had run
+    fn module(code: &str, names: &[&str]) -> Module {
+        let (toks, _) = Lexer::new(code).tokenize();
+        let (prog, diags) = Parser::new(toks).parse_program();
+        assert!(diags.is_empty(), "{:?}", diags);
+        let env = Environment::new();
+        for name in names {
+            env.define(name, Value::Num(1.0));
+        }
+        Module::new(env, &prog)
+    }
+
+    // an empty directory of its own for each test
+    fn scratch(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("zeet-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn names_starting_with_underscore_are_private() {
+        let m = module("let a = 1\nlet _b = 2\n", &["a", "_b"]);
+        assert!(m.export("a").is_ok());
+        assert!(matches!(m.export("_b"), Err(ImportError::Private)));
+        assert!(matches!(m.export("c"), Err(ImportError::Missing)));
+    }
+
+    #[test]
+    fn exporting_anything_makes_the_rest_private() {
+        let m = module("export let a = 1\nlet b = 2\nexport __fn f = ()\n  ret 1\n__\n", &["a", "b", "f"]);
+        assert!(m.export("a").is_ok());
+        assert!(m.export("f").is_ok());
+        assert!(matches!(m.export("b"), Err(ImportError::Private)));
+        let public: Vec<String> = m.public().into_iter().map(|(n, _)| n).collect();
+        assert_eq!(public, ["a", "f"]);
+    }
+
+    #[test]
+    fn modules_are_found_next_to_the_importer_first() {
+        let dir = scratch("resolve");
+        let (here, elsewhere) = (dir.join("here"), dir.join("elsewhere"));
+        fs::create_dir_all(&here).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        fs::write(here.join("utils.zt"), "").unwrap();
+        fs::write(elsewhere.join("utils.zt"), "").unwrap();
+        fs::write(elsewhere.join("extra.zt"), "").unwrap();
+
+        let modules = Modules { search_path: vec![elsewhere.clone()], ..Modules::default() };
+        let importer = here.join("main.zt");
+        assert_eq!(modules.resolve("utils", Some(&importer)), Ok(here.join("utils.zt")));
+        assert_eq!(modules.resolve("extra", Some(&importer)), Ok(elsewhere.join("extra.zt")));
+        assert_eq!(modules.resolve("missing", Some(&importer)), Err(vec![here.join("missing.zt"), elsewhere.join("missing.zt")]));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cycles_are_reported_with_the_chain_of_imports() {
+        let mut modules = Modules::default();
+        let (a, b) = (Path::new("/m/a.zt").to_path_buf(), Path::new("/m/b.zt").to_path_buf());
+        modules.enter(a.clone(), "a.zt".into()).unwrap();
+        modules.enter(b.clone(), "b".into()).unwrap();
+        assert_eq!(modules.enter(a.clone(), "a".into()), Err("a.zt -> b -> a".to_string()));
+        // once `b` is done, importing it again is no longer a cycle
+        modules.leave();
+        assert_eq!(modules.enter(b, "b".into()), Ok(()));
+    }
+}
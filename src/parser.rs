@@ -1,235 +1,946 @@
-use crate::token::Token;
-use crate::ast::{Expr, Literal, Stmt};
-use std::iter::Peekable;
-use std::vec::IntoIter;
+use crate::token::{SpannedToken, Token};
+use crate::ast::{Expr, ExprKind, Imported, Literal, Stmt, StmtKind, Type, PREDICATE_NAMES, TYPE_NAMES};
+use crate::diagnostic::Diagnostic;
+use crate::span::Span;
+
+// boxed so that every Result in the parser stays small
+type PResult<T> = Result<T, Box<Diagnostic>>;
 
 pub struct Parser {
-    tokens: Peekable<IntoIter<Token>>,
+    tokens: Vec<SpannedToken>,
+    pos: usize,
+    // everything reported so far. errors are returned through PResult and
+    // recorded here by whichever statement loop recovers from them
+    diagnostics: Vec<Diagnostic>,
+    // blocks whose header was skipped during recovery; their '__' is still ahead
+    broken_blocks: usize,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens: tokens.into_iter().peekable() }
+    pub fn new(tokens: Vec<SpannedToken>) -> Self {
+        Parser { tokens, pos: 0, diagnostics: Vec::new(), broken_blocks: 0 }
+    }
+
+    fn peek(&self) -> Token {
+        self.peek_at(0)
     }
 
-    fn peek(&mut self) -> Token {
-        self.tokens.peek().cloned().unwrap_or(Token::Eof)
+    // look `offset` tokens past the current one without consuming anything
+    fn peek_at(&self, offset: usize) -> Token {
+        self.tokens.get(self.pos + offset).map(|t| t.token.clone()).unwrap_or(Token::Eof)
     }
 
     fn next(&mut self) -> Token {
-        self.tokens.next().unwrap_or(Token::Eof)
+        let tok = self.peek();
+        if self.pos < self.tokens.len() {
+            self.pos += 1;
+        }
+        tok
+    }
+
+    // span of the current (not yet consumed) token
+    fn span(&self) -> Span {
+        self.tokens.get(self.pos).or(self.tokens.last()).map(|t| t.span).unwrap_or_default()
+    }
+
+    // span of the most recently consumed token
+    fn prev_span(&self) -> Span {
+        match self.pos {
+            0 => self.span(),
+            p => self.tokens[p - 1].span,
+        }
+    }
+
+    // span from `start` up to the end of the last consumed token
+    fn span_since(&self, start: Span) -> Span {
+        start.to(self.prev_span())
     }
 
-    pub fn parse_program(&mut self) -> Vec<Stmt> {
+    // true when the current token sits on the same line as the one before it
+    fn on_same_line(&self) -> bool {
+        self.pos > 0 && self.span().line == self.prev_span().line
+    }
+
+    // "expected X, found Y" at the current token
+    fn unexpected(&self, expected: &str) -> Box<Diagnostic> {
+        Box::new(
+            Diagnostic::error("E0201", format!("expected {}, found {}", expected, self.peek()), self.span())
+                .with_label(format!("expected {}", expected)),
+        )
+    }
+
+    fn expect(&mut self, tok: Token, expected: &str) -> PResult<Span> {
+        if self.peek() == tok {
+            self.next();
+            Ok(self.prev_span())
+        } else {
+            Err(self.unexpected(expected))
+        }
+    }
+
+    fn expect_ident(&mut self, expected: &str) -> PResult<String> {
+        match self.peek() {
+            Token::Identifier(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.unexpected(expected)),
+        }
+    }
+
+    // a block that reached the end of the file without its '__'
+    fn unclosed(&self, what: &str, opened: Span) -> Box<Diagnostic> {
+        Box::new(
+            Diagnostic::error("E0202", format!("unclosed {}", what), self.span())
+                .with_label("expected `__` before the end of the file")
+                .with_secondary(opened, format!("{} starts here", what)),
+        )
+    }
+
+    // the program plus everything worth reporting about it. a syntax error does not
+    // stop the parser: it is recorded, the broken statement is skipped and parsing
+    // carries on, so the statements are only fit to run when no error is present.
+    pub fn parse_program(&mut self) -> (Vec<Stmt>, Vec<Diagnostic>) {
         let mut stmts = Vec::new();
-        loop {
-            match self.peek() {
-                Token::Eof => break,
-                Token::Import => {
-                    let imp = self.parse_import();
-                    stmts.push(Stmt::ImportStmt(imp));
+        while self.peek() != Token::Eof {
+            if self.peek() == Token::Underscore && self.broken_blocks > 0 {
+                self.close_broken_block();
+                continue;
+            }
+            if self.skip_broken_otherwise() {
+                continue;
+            }
+            let from = self.pos;
+            match self.parse_top_level() {
+                Ok(st) => stmts.push(st),
+                Err(d) => self.recover(*d, from),
+            }
+        }
+        (stmts, std::mem::take(&mut self.diagnostics))
+    }
+
+    // parse one statement of a block into `out`, recovering from any syntax error in it
+    fn parse_block_statement(&mut self, out: &mut Vec<Stmt>) {
+        if self.skip_broken_otherwise() {
+            return;
+        }
+        let from = self.pos;
+        match self.parse_statement() {
+            Ok(st) => out.push(st),
+            Err(d) => self.recover(*d, from),
+        }
+    }
+
+    // record a syntax error in the statement starting at token `from`, then skip
+    // the rest of it: everything up to the next line, `__`, `import` or `__fn`
+    fn recover(&mut self, error: Diagnostic, from: usize) {
+        self.diagnostics.push(error);
+        if self.pos == from {
+            self.next();
+        }
+        let line = self.prev_span().line;
+        while !matches!(self.peek(), Token::Eof | Token::Underscore | Token::Import | Token::FnKw)
+            && self.span().line == line
+        {
+            self.next();
+        }
+        // a skipped `if` / loop / `__fn` header opened a block whose body is now parsed
+        // as part of the surrounding code, so its '__' must not close anything
+        let opened = open_blocks(&self.tokens[from..self.pos]);
+        self.broken_blocks = self.broken_blocks.saturating_add_signed(opened);
+    }
+
+    // an `otherwise` line of an if whose header was skipped
+    fn skip_broken_otherwise(&mut self) -> bool {
+        if self.peek() != Token::Otherwise || self.broken_blocks == 0 {
+            return false;
+        }
+        let line = self.span().line;
+        while self.peek() != Token::Eof && self.span().line == line {
+            self.next();
+        }
+        true
+    }
+
+    fn close_broken_block(&mut self) {
+        self.next();
+        self.broken_blocks -= 1;
+    }
+
+    fn parse_top_level(&mut self) -> PResult<Stmt> {
+        if let Token::Import = self.peek() {
+            let start = self.span();
+            let imp = self.parse_import()?;
+            return Ok(Stmt::new(StmtKind::Import(imp), self.span_since(start)));
+        }
+        if let Token::Export = self.peek() {
+            let start = self.span();
+            self.next(); // export
+            let inner = self.parse_statement()?;
+            let named = matches!(
+                &inner.kind,
+                StmtKind::Let { .. } | StmtKind::FunctionDef(Expr { kind: ExprKind::Function { name: Some(_), .. }, .. })
+            );
+            if !named {
+                return Err(Box::new(
+                    Diagnostic::error("E0201", "only definitions can be exported", inner.span)
+                        .with_label("this does not define a name")
+                        .with_help("export a `let`, a `const` or a named `__fn`"),
+                ));
+            }
+            return Ok(Stmt::new(StmtKind::Export(Box::new(inner)), self.span_since(start)));
+        }
+        self.parse_statement()
+    }
+
+    // a single statement inside a program, function or block
+    fn parse_statement(&mut self) -> PResult<Stmt> {
+        let start = self.span();
+        let kind = match self.peek() {
+            Token::FnKw => {
+                let f = self.parse_function(None)?;
+                if let ExprKind::Function { name: None, .. } = f.kind {
+                    self.diagnostics.push(
+                        Diagnostic::warning("W0201", "this function has no name and can never be called", f.span)
+                            .with_help("give it a name: `__fn name = (...)` or `name = __fn (...)`"),
+                    );
+                }
+                StmtKind::FunctionDef(f)
+            }
+            // name = __fn (...)
+            Token::Identifier(name) if self.peek_at(1) == Token::Equals && self.peek_at(2) == Token::FnKw => {
+                self.next(); // name
+                self.next(); // =
+                StmtKind::FunctionDef(self.parse_function(Some(name))?)
+            }
+            // name = expr
+            Token::Identifier(name) if self.peek_at(1) == Token::Equals => {
+                self.next(); // name
+                self.next(); // =
+                let value = self.parse_expr()?;
+                let assign = ExprKind::Assign { name, value: Box::new(value) };
+                StmtKind::Expr(Expr::new(assign, self.span_since(start)))
+            }
+            Token::Let | Token::Const => {
+                let constant = self.next() == Token::Const;
+                let name = self.expect_ident("a variable name")?;
+                self.expect(Token::Equals, "`=`")?;
+                let value = self.parse_expr()?;
+                StmtKind::Let { name, value, constant }
+            }
+            Token::If => StmtKind::Expr(self.parse_if()?),
+            Token::While => {
+                self.next();
+                let cond = self.parse_expr()?;
+                self.expect_do();
+                StmtKind::While { cond, body: self.parse_block("while loop", start)? }
+            }
+            Token::Each => {
+                self.next();
+                let var = self.expect_ident("a loop variable name")?;
+                self.expect(Token::In, "`in`")?;
+                let iter = self.parse_expr()?;
+                self.expect_do();
+                StmtKind::Each { var, iter, body: self.parse_block("each loop", start)? }
+            }
+            Token::Repeat => {
+                self.next();
+                let count = self.parse_expr()?;
+                self.expect_do();
+                StmtKind::Repeat { count, body: self.parse_block("repeat loop", start)? }
+            }
+            Token::Stop => { self.next(); StmtKind::Stop }
+            Token::Skip => { self.next(); StmtKind::Skip }
+            Token::Run => {
+                self.next();
+                let expr = self.parse_expr()?;
+                StmtKind::Expr(Expr::new(ExprKind::Run(Box::new(expr)), self.span_since(start)))
+            }
+            Token::Ret => {
+                self.next();
+                let expr = self.parse_expr()?;
+                StmtKind::Expr(Expr::new(ExprKind::Return(Box::new(expr)), self.span_since(start)))
+            }
+            Token::Identifier(_) | Token::StringLit(_) | Token::NumberLit(_) | Token::BoolLit(_) | Token::NullLit
+            | Token::LParen | Token::LBracket | Token::LBrace | Token::Minus => {
+                let target = self.parse_expr()?;
+                if self.peek() != Token::Equals {
+                    StmtKind::Expr(target)
+                } else {
+                    StmtKind::Expr(self.parse_target_assign(target)?)
                 }
-                Token::FnKw => {
-                    let f = self.parse_function();
-                    stmts.push(Stmt::FunctionDef(f));
+            }
+            Token::Underscore => {
+                return Err(Box::new(
+                    Diagnostic::error("E0201", "unexpected `__`", self.span())
+                        .with_label("there is no open block to close here"),
+                ));
+            }
+            Token::Import | Token::Export => {
+                let word = self.peek();
+                return Err(Box::new(
+                    Diagnostic::error("E0201", format!("{} is only allowed at the top level of a file", word), self.span())
+                        .with_label("inside a block"),
+                ));
+            }
+            _ => return Err(self.unexpected("a statement")),
+        };
+        Ok(Stmt::new(kind, self.span_since(start)))
+    }
+
+    // `<target> = value` where the target is an element such as `list at 0`
+    // or a field such as `user.name`
+    fn parse_target_assign(&mut self, target: Expr) -> PResult<Expr> {
+        let kind = match target.kind {
+            ExprKind::Index { target: t, index } => {
+                self.next(); // =
+                let value = Box::new(self.parse_expr()?);
+                ExprKind::SetIndex { target: t, index, value }
+            }
+            ExprKind::Field { target: t, name } => {
+                self.next(); // =
+                let value = Box::new(self.parse_expr()?);
+                ExprKind::SetField { target: t, name, value }
+            }
+            _ => {
+                return Err(Box::new(
+                    Diagnostic::error("E0201", "cannot assign to this expression", target.span)
+                        .with_label("not a variable, element or field")
+                        .with_help("assign to a name (`x = ...`), an element (`list at 0 = ...`) or a field (`user.name = ...`)"),
+                ));
+            }
+        };
+        Ok(Expr::new(kind, self.span_since(target.span)))
+    }
+
+    // import a [-> alias], b ... from module
+    // import * from module
+    // import module [-> alias]
+    fn parse_import(&mut self) -> PResult<Expr> {
+        let start = self.span();
+        self.next(); // consume import
+        let what = match self.peek() {
+            Token::Star => {
+                self.next();
+                Imported::All
+            }
+            Token::StringLit(_) => {
+                let module = self.parse_module_name()?;
+                let alias = self.parse_import_alias()?;
+                return Ok(Expr::new(ExprKind::Import { what: Imported::Module(alias), module }, self.span_since(start)));
+            }
+            _ => {
+                let name = self.expect_ident("a name or a module to import")?;
+                let alias = self.parse_import_alias()?;
+                // without `from` or a list, the name is the module itself
+                if !matches!(self.peek(), Token::From | Token::Comma) {
+                    return Ok(Expr::new(ExprKind::Import { what: Imported::Module(alias), module: name }, self.span_since(start)));
                 }
-                _ => {
-                    // skip unknown top-level tokens
+                let mut names = vec![(name, alias)];
+                while self.peek() == Token::Comma {
                     self.next();
+                    let name = self.expect_ident("a name to import")?;
+                    names.push((name, self.parse_import_alias()?));
                 }
+                Imported::Names(names)
             }
+        };
+        self.expect(Token::From, "`from`")?;
+        let module = self.parse_module_name()?;
+        Ok(Expr::new(ExprKind::Import { what, module }, self.span_since(start)))
+    }
+
+    fn parse_import_alias(&mut self) -> PResult<Option<String>> {
+        if self.peek() != Token::Arrow {
+            return Ok(None);
         }
-        stmts
+        self.next(); // ->
+        Ok(Some(self.expect_ident("an alias after `->`")?))
     }
 
-    fn parse_import(&mut self) -> Expr {
-        // import name [-> alias] from module
-        self.next(); // consume import
-        let name = if let Token::Identifier(s) = self.next() { s } else { "".into() };
-        let mut alias = None;
-        if let Token::Arrow = self.peek() {
-            self.next(); // ->
-            if let Token::Identifier(a) = self.next() { alias = Some(a); }
-        }
-        // expect from
-        if let Token::From = self.next() {} // consume
-        let module = if let Token::Identifier(m) = self.next() {
-            m
-        } else if let Token::StringLit(s) = self.next() {
-            s
-        } else { String::new() };
-        Expr::Import { name, alias, module }
-    }
-
-    fn parse_function(&mut self) -> Expr {
+    fn parse_module_name(&mut self) -> PResult<String> {
+        match self.next() {
+            Token::Identifier(m) => Ok(m),
+            Token::StringLit(s) => Ok(s),
+            _ => {
+                self.pos -= 1;
+                Err(self.unexpected("a module name"))
+            }
+        }
+    }
+
+    fn parse_function(&mut self, mut name: Option<String>) -> PResult<Expr> {
+        let start = self.span();
         self.next(); // consume __fn
+        // `__fn name = (...)` form
+        if let (Token::Identifier(n), Token::Equals) = (self.peek(), self.peek_at(1)) {
+            self.next();
+            name = Some(n);
+        }
         // optional '='
         if let Token::Equals = self.peek() {
             self.next();
         }
-        // expect '(' params ')'
+        // '(' params ')'
         let mut params = Vec::new();
-        if let Token::LParen = self.next() {
-            loop {
-                match self.peek() {
-                    Token::Identifier(s) => {
-                        if let Token::Identifier(name) = self.next() {
-                            params.push(name);
-                        }
-                    }
-                    Token::Comma => { self.next(); }
-                    Token::RParen => { self.next(); break; }
-                    _ => { self.next(); }
-                }
+        self.expect(Token::LParen, "`(` to start the parameter list")?;
+        while self.peek() != Token::RParen {
+            params.push(self.expect_ident("a parameter name")?);
+            if self.peek() != Token::RParen {
+                self.expect(Token::Comma, "`,` or `)`")?;
             }
         }
-        // optional type annotations like : < ... >
-        let mut types = Vec::new();
+        self.next(); // ')'
+        // optional type annotations like : <a is string, b is optional number Array>
+        let mut types: Vec<(String, Type)> = Vec::new();
         if let Token::Colon = self.peek() {
             self.next(); // :
-            if let Token::LAngle = self.next() {
-                // parse simple "a is string, b is string Array" until '>'
-                loop {
-                    match self.peek() {
-                        Token::Identifier(name) => {
-                            if let Token::Identifier(param_name) = self.next() {
-                                // expect 'is'
-                                if let Token::Identifier(is_kw) = self.peek() {
-                                    if is_kw.to_lowercase() == "is" {
-                                        self.next(); // consume 'is'
-                                        if let Token::Identifier(typ) = self.next() {
-                                            types.push((param_name, typ));
-                                        } else {
-                                            // skip
-                                            self.next();
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                        Token::Comma => { self.next(); }
-                        Token::RAngle => { self.next(); break; }
-                        Token::Identifier(_) => { self.next(); }
-                        _ => { self.next(); }
-                    }
+            self.expect(Token::LAngle, "`<` to start the type annotations")?;
+            while self.peek() != Token::RAngle {
+                let name_span = self.span();
+                let param_name = self.expect_ident("a parameter name")?;
+                if self.peek_word(0) != Some("is") {
+                    return Err(self.unexpected("`is`"));
+                }
+                self.next(); // consume 'is'
+                let typ = self.parse_type()?;
+                if !params.contains(&param_name) {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0204", format!("`{}` is not a parameter of this function", param_name), name_span)
+                            .with_label("no such parameter"),
+                    );
+                } else if types.iter().any(|(p, _)| *p == param_name) {
+                    self.diagnostics.push(
+                        Diagnostic::error("E0204", format!("`{}` already has a type", param_name), name_span)
+                            .with_label("second annotation for this parameter"),
+                    );
+                } else {
+                    types.push((param_name, typ));
+                }
+                if self.peek() != Token::RAngle {
+                    self.expect(Token::Comma, "`,` or `>`")?;
                 }
             }
+            self.next(); // '>'
         }
 
         // parse body lines until Underscore token '__' (function end)
-        let mut body: Vec<Stmt> = Vec::new();
+        let body = self.parse_block("function", start)?;
+
+        Ok(Expr::new(ExprKind::Function { name, params, types, body }, self.span_since(start)))
+    }
+
+    // type := alternative ('or' alternative)*  (an `is` test takes a single alternative)
+    // alternative := 'optional'? name 'Array'*
+    fn parse_type(&mut self) -> PResult<Type> {
+        let mut options = vec![self.parse_type_alternative()?];
+        while self.peek_word(0) == Some("or") {
+            self.next();
+            options.push(self.parse_type_alternative()?);
+        }
+        Ok(if options.len() == 1 { options.remove(0) } else { Type::Union(options) })
+    }
+
+    // besides the types, any predicate name is accepted, since the host program can
+    // register predicates the parser has never heard of; whether it exists is only
+    // known when the code runs, so a name we do not know is just a warning
+    fn parse_type_alternative(&mut self) -> PResult<Type> {
+        if self.peek_word(0) == Some("optional") {
+            self.next();
+            return Ok(Type::Optional(Box::new(self.parse_type_alternative()?)));
+        }
+        let span = self.span();
+        // `null` is a literal elsewhere, but also names its own type
+        let name = if self.peek() == Token::NullLit {
+            self.next();
+            "null".to_string()
+        } else {
+            self.expect_ident("a type name")?
+        };
+        let mut typ = match name.as_str() {
+            // a bare `Array` holds anything
+            "Array" => Type::Array(Box::new(Type::Named("any".into()))),
+            n if TYPE_NAMES.contains(&n) || PREDICATE_NAMES.contains(&n) => Type::Named(name),
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::warning("W0202", format!("unknown type or predicate `{}`", name), span)
+                        .with_label("not a type or a built-in predicate")
+                        .with_note(format!(
+                            "the types are {}, plus `Array` after any of them, and the predicates are {}",
+                            TYPE_NAMES.join(", "),
+                            PREDICATE_NAMES.join(", ")
+                        ))
+                        .with_help("unless the host program registers this predicate, using it fails when the code runs"),
+                );
+                Type::Named(name)
+            }
+        };
+        while self.peek_word(0) == Some("Array") {
+            self.next();
+            typ = Type::Array(Box::new(typ));
+        }
+        Ok(typ)
+    }
+
+    // statements up to and including the closing '__'; `what` and `opened`
+    // describe the construct for the error when the '__' is missing
+    fn parse_block(&mut self, what: &str, opened: Span) -> PResult<Vec<Stmt>> {
+        let mut body = Vec::new();
         loop {
             match self.peek() {
+                Token::Underscore if self.broken_blocks > 0 => self.close_broken_block(),
                 Token::Underscore => { self.next(); break; }
-                Token::If => {
-                    let ifstmt = self.parse_if();
-                    body.push(Stmt::Expr(ifstmt));
-                }
-                Token::Run => {
-                    self.next();
-                    let expr = self.parse_simple_expr();
-                    body.push(Stmt::Expr(Expr::Run(Box::new(expr))));
-                }
-                Token::Ret => {
-                    self.next();
-                    let expr = self.parse_simple_expr();
-                    body.push(Stmt::Expr(Expr::Return(Box::new(expr))));
-                }
-                Token::Eof => break,
-                _ => { self.next(); } // skip unknown
+                Token::Eof => return Err(self.unclosed(what, opened)),
+                _ => self.parse_block_statement(&mut body),
             }
         }
+        Ok(body)
+    }
 
-        Expr::Function { params, types, body }
+    // loop headers end with "- do," (or a bare "do")
+    fn expect_do(&mut self) {
+        if let Token::Do = self.peek() { self.next(); }
     }
 
-    fn parse_if(&mut self) -> Expr {
+    // if cond - then, ... [otherwise if cond - then, ...]* [otherwise ...] __
+    // a whole chain is closed by a single '__'
+    fn parse_if(&mut self) -> PResult<Expr> {
+        let start = self.span();
         self.next(); // consume if
         // parse condition inside parentheses or simple tokens until Then
-        let cond = self.parse_simple_expr();
+        let cond = self.parse_expr()?;
         // consume optional Then or "- then," which lexer normalizes to Then token
         if let Token::Then = self.peek() { self.next(); }
-        // parse then-body until Otherwise or Underscore or EOF
+        // parse then-body until Otherwise or Underscore
         let mut then_body = Vec::new();
+        let mut has_otherwise = false;
         loop {
             match self.peek() {
                 Token::Otherwise => {
                     self.next(); // consume otherwise
+                    has_otherwise = true;
                     break;
                 }
-                Token::Ret => {
-                    self.next();
-                    let expr = self.parse_simple_expr();
-                    then_body.push(Stmt::Expr(Expr::Return(Box::new(expr))));
-                }
-                Token::Run => {
-                    self.next();
-                    let expr = self.parse_simple_expr();
-                    then_body.push(Stmt::Expr(Expr::Run(Box::new(expr))));
-                }
-                Token::Underscore | Token::Eof => break,
-                _ => { self.next(); } // skip
+                Token::Underscore if self.broken_blocks > 0 => self.close_broken_block(),
+                // a '__' closes an if that has no otherwise branch
+                Token::Underscore => { self.next(); break; }
+                Token::Eof => return Err(self.unclosed("if block", start)),
+                _ => self.parse_block_statement(&mut then_body),
             }
         }
 
-        // optional else part after 'otherwise'
         let mut else_body = None;
-        if let Token::Ret = self.peek() {
-            self.next();
-            let expr = self.parse_simple_expr();
-            else_body = Some(vec![Stmt::Expr(Expr::Return(Box::new(expr)))]);
-        } else if let Token::Identifier(_) = self.peek() {
-            // some syntaxes might put 'ret' after 'otherwise -', handled above
+        if has_otherwise {
+            if self.peek() == Token::If && self.on_same_line() {
+                // `otherwise if`: the rest of the chain is an if of its own, and its '__' ends ours
+                let nested = self.parse_if()?;
+                let span = nested.span;
+                else_body = Some(vec![Stmt::new(StmtKind::Expr(nested), span)]);
+            } else {
+                // "otherwise - ...": the dash is just punctuation
+                if matches!(self.peek(), Token::Minus | Token::Then) { self.next(); }
+                else_body = Some(self.parse_block("otherwise block", start)?);
+            }
+        }
+
+        Ok(Expr::new(ExprKind::If { cond: Box::new(cond), then_body, else_body }, self.span_since(start)))
+    }
+
+    // precedence climbing over the word operators and their symbolic aliases, loosest first:
+    //   or < and < not < comparisons < plus/minus < times/divided by/mod < at < unary minus < calls/[]
+    fn parse_expr(&mut self) -> PResult<Expr> {
+        self.parse_binary(PREC_OR)
+    }
+
+    fn parse_binary(&mut self, min_prec: u8) -> PResult<Expr> {
+        let mut left = self.parse_operand(min_prec)?;
+        while let Some((op, prec, len)) = self.peek_binary_op() {
+            if prec < min_prec {
+                break;
+            }
+            for _ in 0..len { self.next(); }
+            if op == "at" {
+                let index = self.parse_binary(prec + 1)?;
+                let span = left.span.to(index.span);
+                left = Expr::new(ExprKind::Index { target: Box::new(left), index: Box::new(index) }, span);
+                continue;
+            }
+            // `is` takes a type or predicate name rather than an expression
+            if op == "is" {
+                let ty = self.parse_type_alternative()?;
+                let span = self.span_since(left.span);
+                left = Expr::new(ExprKind::Is { expr: Box::new(left), ty }, span);
+                continue;
+            }
+            // all binary operators are left associative
+            let right = self.parse_binary(prec + 1)?;
+            let span = left.span.to(right.span);
+            left = Expr::new(ExprKind::Binary { left: Box::new(left), op: op.into(), right: Box::new(right) }, span);
         }
+        Ok(left)
+    }
 
-        Expr::If { cond: Box::new(cond), then_body, else_body }
+    fn parse_operand(&mut self, min_prec: u8) -> PResult<Expr> {
+        // `not` reads like english: `not a same b` is `not (a same b)`
+        if min_prec <= PREC_CMP && self.peek_word(0) == Some("not") && self.peek_word(1) != Some("equal") {
+            let start = self.span();
+            self.next();
+            let expr = self.parse_binary(PREC_CMP)?;
+            return Ok(Expr::new(ExprKind::Unary { op: "not".into(), expr: Box::new(expr) }, self.span_since(start)));
+        }
+        self.parse_unary()
     }
 
-    // extremely simple expression parser that recognizes:
-    // identifiers, literals, and binary ops with keyword operators (plus, and, same, not equal)
-    fn parse_simple_expr(&mut self) -> Expr {
-        // get first operand
-        let left = match self.next() {
-            Token::Identifier(s) => {
-                Expr::Var(s)
+    fn parse_unary(&mut self) -> PResult<Expr> {
+        let start = self.span();
+        if let Token::Minus = self.peek() {
+            self.next();
+            let expr = self.parse_unary()?;
+            return Ok(Expr::new(ExprKind::Unary { op: "negate".into(), expr: Box::new(expr) }, self.span_since(start)));
+        }
+        if self.peek_word(0) == Some("not") {
+            self.next();
+            let expr = self.parse_unary()?;
+            return Ok(Expr::new(ExprKind::Unary { op: "not".into(), expr: Box::new(expr) }, self.span_since(start)));
+        }
+        if self.peek_word(0) == Some("length") && self.peek_word(1) == Some("of") {
+            self.next();
+            self.next();
+            let expr = self.parse_unary()?;
+            return Ok(Expr::new(ExprKind::Unary { op: "length".into(), expr: Box::new(expr) }, self.span_since(start)));
+        }
+        // `name of user` reads a field; `city of address of user` nests to the right
+        if let (Some(field), Some("of")) = (self.peek_word(0), self.peek_word(1)) {
+            let name = field.to_string();
+            self.next();
+            self.next();
+            let target = self.parse_unary()?;
+            return Ok(Expr::new(ExprKind::Field { target: Box::new(target), name }, self.span_since(start)));
+        }
+        let mut expr = self.parse_primary()?;
+        // postfix calls, indexing and fields. only things that may hold a function can be
+        // called, and the '(' or '[' has to be on the same line; otherwise it starts the
+        // next statement
+        loop {
+            match self.peek() {
+                Token::LParen if self.on_same_line() && is_callable(&expr.kind) => {
+                    expr = self.parse_call(expr)?;
+                }
+                Token::LBracket if self.on_same_line() => {
+                    let open = self.span();
+                    self.next();
+                    let index = self.parse_expr()?;
+                    self.expect_closing(Token::RBracket, "`]`", open)?;
+                    let span = self.span_since(expr.span);
+                    expr = Expr::new(ExprKind::Index { target: Box::new(expr), index: Box::new(index) }, span);
+                }
+                Token::Dot => {
+                    self.next();
+                    let name = self.expect_ident("a field name after `.`")?;
+                    let span = self.span_since(expr.span);
+                    expr = Expr::new(ExprKind::Field { target: Box::new(expr), name }, span);
+                }
+                _ => break,
             }
-            Token::StringLit(s) => Expr::Lit(Literal::Str(s)),
-            Token::NumberLit(n) => Expr::Lit(Literal::Num(n)),
-            Token::BoolLit(b) => Expr::Lit(Literal::Bool(b)),
+        }
+        Ok(expr)
+    }
+
+    fn parse_primary(&mut self) -> PResult<Expr> {
+        let start = self.span();
+        let kind = match self.peek() {
+            Token::Identifier(s) => ExprKind::Var(s),
+            Token::StringLit(s) => ExprKind::Lit(Literal::Str(s)),
+            Token::NumberLit(n) => ExprKind::Lit(Literal::Num(n)),
+            Token::BoolLit(b) => ExprKind::Lit(Literal::Bool(b)),
+            Token::NullLit => ExprKind::Lit(Literal::Null),
             Token::LParen => {
-                // not heavy parsing: read until RParen as a single identifier or literal
-                if let Token::Identifier(s) = self.next() {
-                    let _ = self.next(); // consume ')'
-                    Expr::Var(s)
-                } else { Expr::Lit(Literal::Bool(false)) }
+                self.next();
+                let inner = self.parse_expr()?;
+                self.expect_closing(Token::RParen, "`)`", start)?;
+                return Ok(Expr::new(inner.kind, self.span_since(start)));
             }
-            other => {
-                // unknown; return false literal
-                Expr::Lit(Literal::Bool(false))
+            Token::LBracket => {
+                self.next();
+                let mut items = Vec::new();
+                while self.peek() != Token::RBracket {
+                    items.push(self.parse_expr()?);
+                    if self.peek() != Token::RBracket {
+                        self.expect_closing(Token::Comma, "`,` or `]`", start)?;
+                    }
+                }
+                self.next(); // ']'
+                return Ok(Expr::new(ExprKind::Array(items), self.span_since(start)));
             }
+            Token::LBrace => return self.parse_record(),
+            // an anonymous function used as a value: `let double = __fn (x) ret x times 2 __`
+            Token::FnKw => return self.parse_function(None),
+            _ => return Err(self.unexpected("an expression")),
         };
+        self.next();
+        Ok(Expr::new(kind, self.span_since(start)))
+    }
 
-        // lookahead for operator
-        match self.peek() {
-            Token::Identifier(op) => {
-                // operators like plus, and, same, not...
-                let op_word = if let Token::Identifier(op2) = self.next() { op2 } else { String::new() };
-                // handle 'not equal' sequence
-                if op_word == "not" {
-                    if let Token::Identifier(next_word) = self.peek() {
-                        if next_word == "equal" {
-                            let _ = self.next(); // consume 'equal'
-                            // parse right operand
-                            let right = self.parse_simple_expr();
-                            return Expr::Binary { left: Box::new(left), op: "not_equal".into(), right: Box::new(right) };
-                        }
-                    }
+    // { key: value, "other key": value }
+    fn parse_record(&mut self) -> PResult<Expr> {
+        let start = self.span();
+        self.next(); // '{'
+        let mut fields: Vec<(String, Expr)> = Vec::new();
+        while self.peek() != Token::RBrace {
+            let key_span = self.span();
+            let key = match self.peek() {
+                Token::Identifier(k) | Token::StringLit(k) => {
+                    self.next();
+                    k
                 }
-                // else normal binary operator
-                let right = self.parse_simple_expr();
-                Expr::Binary { left: Box::new(left), op: op_word, right: Box::new(right) }
+                _ => return Err(self.unexpected("a field name")),
+            };
+            self.expect(Token::Colon, "`:` after the field name")?;
+            let value = self.parse_expr()?;
+            if fields.iter().any(|(k, _)| *k == key) {
+                self.diagnostics.push(
+                    Diagnostic::error("E0205", format!("field `{}` is given twice", key), key_span)
+                        .with_label("duplicate field"),
+                );
+            } else {
+                fields.push((key, value));
             }
-            _ => left,
+            if self.peek() != Token::RBrace {
+                self.expect_closing(Token::Comma, "`,` or `}`", start)?;
+            }
+        }
+        self.next(); // '}'
+        Ok(Expr::new(ExprKind::Record(fields), self.span_since(start)))
+    }
+
+    // like `expect`, but points back at the opening delimiter when it is missing
+    fn expect_closing(&mut self, tok: Token, expected: &str, opened: Span) -> PResult<Span> {
+        self.expect(tok, expected).map_err(|d| Box::new(d.with_secondary(opened, "opened here")))
+    }
+
+    fn peek_word(&self, offset: usize) -> Option<&str> {
+        match self.tokens.get(self.pos + offset).map(|t| &t.token) {
+            Some(Token::Identifier(w)) => Some(w.as_str()),
+            _ => None,
+        }
+    }
+
+    // the binary operator starting at the current token, if any:
+    // (canonical name, precedence, number of tokens it spans)
+    fn peek_binary_op(&self) -> Option<(&'static str, u8, usize)> {
+        let op = match self.peek() {
+            Token::Plus => ("plus", PREC_ADD, 1),
+            // a '-' that starts a new line is a unary minus in the next statement
+            Token::Minus if self.on_same_line() => ("minus", PREC_ADD, 1),
+            Token::Star => ("times", PREC_MUL, 1),
+            Token::Slash => ("divided_by", PREC_MUL, 1),
+            Token::LAngle => ("less_than", PREC_CMP, 1),
+            Token::RAngle => ("greater_than", PREC_CMP, 1),
+            Token::LtEq => ("at_most", PREC_CMP, 1),
+            Token::GtEq => ("at_least", PREC_CMP, 1),
+            Token::EqEq => ("same", PREC_CMP, 1),
+            Token::NotEq => ("not_equal", PREC_CMP, 1),
+            Token::Identifier(_) => match (self.peek_word(0).unwrap_or(""), self.peek_word(1)) {
+                ("or", _) => ("or", PREC_OR, 1),
+                ("and", _) => ("and", PREC_AND, 1),
+                ("same", _) => ("same", PREC_CMP, 1),
+                ("is", _) => ("is", PREC_CMP, 1),
+                ("not", Some("equal")) => ("not_equal", PREC_CMP, 2),
+                ("greater", Some("than")) => ("greater_than", PREC_CMP, 2),
+                ("less", Some("than")) => ("less_than", PREC_CMP, 2),
+                ("at", Some("least")) => ("at_least", PREC_CMP, 2),
+                ("at", Some("most")) => ("at_most", PREC_CMP, 2),
+                ("at", _) => ("at", PREC_INDEX, 1),
+                ("plus", _) => ("plus", PREC_ADD, 1),
+                ("minus", _) => ("minus", PREC_ADD, 1),
+                ("times", _) => ("times", PREC_MUL, 1),
+                ("divided", Some("by")) => ("divided_by", PREC_MUL, 2),
+                ("mod", _) => ("mod", PREC_MUL, 1),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // callee(arg, arg, ...) -- the callee has already been parsed, '(' is next
+    fn parse_call(&mut self, callee: Expr) -> PResult<Expr> {
+        let open = self.span();
+        self.next(); // consume '('
+        let mut args = Vec::new();
+        while self.peek() != Token::RParen {
+            args.push(self.parse_expr()?);
+            if self.peek() != Token::RParen {
+                self.expect_closing(Token::Comma, "`,` or `)`", open)?;
+            }
+        }
+        self.next(); // ')'
+        let span = self.span_since(callee.span);
+        Ok(Expr::new(ExprKind::Call { callee: Box::new(callee), args }, span))
+    }
+}
+
+// expressions that may evaluate to a function
+fn is_callable(kind: &ExprKind) -> bool {
+    matches!(kind, ExprKind::Var(_) | ExprKind::Call { .. } | ExprKind::Index { .. } | ExprKind::Field { .. })
+}
+
+const PREC_OR: u8 = 1;
+const PREC_AND: u8 = 2;
+const PREC_CMP: u8 = 3;
+const PREC_ADD: u8 = 4;
+const PREC_MUL: u8 = 5;
+const PREC_INDEX: u8 = 6;
+
+// how many blocks the tokens open without closing them again (negative if they close
+// more than they open); `otherwise if` continues a chain rather than opening a block
+pub fn open_blocks(tokens: &[SpannedToken]) -> isize {
+    let mut opened = 0;
+    for (i, t) in tokens.iter().enumerate() {
+        match t.token {
+            // like `parse_if`, only `otherwise if` on one line continues a chain
+            Token::If if i > 0 && tokens[i - 1].token == Token::Otherwise && tokens[i - 1].span.line == t.span.line => {}
+            Token::If | Token::While | Token::Each | Token::Repeat | Token::FnKw => opened += 1,
+            Token::Underscore => opened -= 1,
+            _ => {}
+        }
+    }
+    opened
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Parser;
+    use crate::ast::{Expr, ExprKind, Literal, Stmt, StmtKind};
+    use crate::lexer::Lexer;
+
+    fn parse(code: &str) -> (Vec<Stmt>, Vec<(String, usize)>) {
+        let (toks, lex_diags) = Lexer::new(code).tokenize();
+        assert!(lex_diags.is_empty(), "{:?}", lex_diags);
+        let (prog, diags) = Parser::new(toks).parse_program();
+        (prog, diags.into_iter().map(|d| (d.code, d.primary.span.line)).collect())
+    }
+
+    fn expected(errors: &[(&str, usize)]) -> Vec<(String, usize)> {
+        errors.iter().map(|(code, line)| (code.to_string(), *line)).collect()
+    }
+
+    // how an expression groups, with every operation in parentheses
+    fn grouping(e: &Expr) -> String {
+        match &e.kind {
+            ExprKind::Binary { left, op, right } => format!("({} {} {})", grouping(left), op, grouping(right)),
+            ExprKind::Unary { op, expr } => format!("({} {})", op, grouping(expr)),
+            ExprKind::Index { target, index } => format!("({} at {})", grouping(target), grouping(index)),
+            ExprKind::Var(name) => name.clone(),
+            ExprKind::Lit(Literal::Num(n)) => n.to_string(),
+            ExprKind::Lit(Literal::Bool(b)) => b.to_string(),
+            other => panic!("unexpected {:?}", other),
         }
     }
-}
\ No newline at end of file
+
+    // the grouping of each expression statement in the code
+    fn groupings(code: &str) -> Vec<String> {
+        let (prog, diags) = parse(code);
+        assert!(diags.is_empty(), "{:?}", diags);
+        prog.iter()
+            .map(|st| match &st.kind {
+                StmtKind::Expr(e) => grouping(e),
+                other => panic!("unexpected {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(groupings("true or false and false"), ["(true or (false and false))"]);
+    }
+
+    #[test]
+    fn not_applies_to_a_whole_comparison() {
+        assert_eq!(groupings("not a same b"), ["(not (a same b))"]);
+    }
+
+    #[test]
+    fn arithmetic_groups_from_the_left() {
+        assert_eq!(groupings("10 minus 4 minus 3"), ["((10 minus 4) minus 3)"]);
+        assert_eq!(groupings("1 plus 2 times 3"), ["(1 plus (2 times 3))"]);
+    }
+
+    #[test]
+    fn indexing_binds_tighter_than_arithmetic() {
+        assert_eq!(groupings("list at i plus 1"), ["((list at i) plus 1)"]);
+    }
+
+    #[test]
+    fn minus_after_an_operator_is_a_sign() {
+        assert_eq!(groupings("-2 times -3"), ["((negate 2) times (negate 3))"]);
+    }
+
+    #[test]
+    fn minus_at_the_start_of_a_line_is_a_sign() {
+        assert_eq!(groupings("y\n-y"), ["y", "(negate y)"]);
+        assert_eq!(groupings("y minus\n  1"), ["(y minus 1)"]);
+    }
+
+    #[test]
+    fn broken_headers_inside_a_function_are_reported_once() {
+        let code = "\
+__fn f = (a)
+  if a plus - then,
+    print(1)
+  otherwise
+    print(3)
+  __
+  while ) do
+    print(2)
+  __
+  __fn g = (, b)
+    ret b
+  __
+  ret a
+__
+let x = = 3
+print(f(1))
+";
+        let (prog, diags) = parse(code);
+        assert_eq!(diags, expected(&[("E0201", 2), ("E0201", 7), ("E0201", 10), ("E0201", 15)]));
+        // the `__` of each broken block does not close `f` early
+        assert_eq!(prog.len(), 2);
+        let StmtKind::FunctionDef(f) = &prog[0].kind else { panic!("{:?}", prog[0]) };
+        let ExprKind::Function { body, .. } = &f.kind else { panic!("{:?}", f) };
+        assert!(matches!(body.last().map(|s| &s.kind), Some(StmtKind::Expr(e)) if matches!(e.kind, ExprKind::Return(_))));
+        assert!(matches!(&prog[1].kind, StmtKind::Expr(e) if matches!(e.kind, ExprKind::Call { .. })));
+    }
+
+    #[test]
+    fn broken_statements_in_a_loop_keep_the_rest() {
+        let code = "each x in [1, 2] do\n  print(x plus)\n  print(x)\n  let = 2\n__\nprint(3)\n";
+        let (prog, diags) = parse(code);
+        assert_eq!(diags, expected(&[("E0201", 2), ("E0201", 4)]));
+        assert_eq!(prog.len(), 2);
+        let StmtKind::Each { body, .. } = &prog[0].kind else { panic!("{:?}", prog[0]) };
+        assert_eq!(body.len(), 1);
+    }
+
+    #[test]
+    fn if_on_the_line_after_otherwise_opens_a_block() {
+        let count = |code: &str| super::open_blocks(&Lexer::new(code).tokenize().0);
+        assert_eq!(count("if a - then,\n  print(1)\notherwise if b - then,\n  print(2)\n"), 1);
+        let code = "if a - then,\n  print(1)\notherwise\n  if true - then,\n    print(2)\n  __\n";
+        assert_eq!(count(code), 1);
+        let (prog, diags) = parse(&format!("{}  print(3)\n__\n", code));
+        assert_eq!(diags, expected(&[]));
+        assert_eq!(prog.len(), 1);
+    }
+
+    #[test]
+    fn unclosed_block_is_reported_once() {
+        let code = "print(1)\n__fn f = (a)\n  if a - then,\n    ret 1\n  __\n  ret 2\n";
+        let (_, diags) = parse(code);
+        assert_eq!(diags, expected(&[("E0202", 7)]));
+        // and points back at the block that is missing its `__`
+        let (toks, _) = Lexer::new(code).tokenize();
+        let (_, diags) = Parser::new(toks).parse_program();
+        assert_eq!(diags[0].secondary[0].span.line, 2);
+    }
+}
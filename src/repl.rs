@@ -0,0 +1,156 @@
+use crate::builtins;
+use crate::stdlib;
+use crate::diagnostic::{report, Source};
+use crate::environment::{Environment, Value};
+use crate::interpreter::Interp;
+use crate::lexer::Lexer;
+use crate::module::analyze;
+use crate::parser::open_blocks;
+use crate::{ast, read_source, report_runtime, tokens, EXIT_USAGE};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+const PROMPT: &str = "zeet> ";
+// shown while a `__fn`, `if` or loop block is still open
+const MORE: &str = "  ... ";
+
+const HELP: &str = "\
+:tokens <code>   print the tokens of the code
+:ast <code>      print the syntax tree of the code
+:env             list what has been defined in this session
+:load <file>     run a file in this session
+:reset           forget everything defined so far
+:help            show this list
+:quit            leave (so does ctrl-d)";
+
+// everything that outlives a single input
+struct Session {
+    interp: Interp,
+    env: Environment,
+}
+
+impl Session {
+    fn new() -> Self {
+        // builtins live in a parent scope so that `:env` only lists the user's names
+        let globals = Environment::new();
+        builtins::install(&globals);
+        let mut interp = Interp::new();
+        stdlib::install(&mut interp);
+        Session { interp, env: globals.child() }
+    }
+
+    // run code in the session; with `show`, the value of a bare expression at the end is printed
+    fn run(&mut self, src: Source, show: bool) {
+        let (prog, diags) = analyze(&src);
+        if report(&diags, &src) {
+            return;
+        }
+        let src = Rc::new(src);
+        self.interp.set_source(src.clone());
+        let result = if show {
+            self.interp.interpret_value(prog, &self.env)
+        } else {
+            self.interp.interpret(prog, &self.env).map(|_| None)
+        };
+        match result {
+            Ok(Some(Value::Null)) | Ok(None) => {}
+            Ok(Some(v)) => println!("{}", v),
+            Err(e) => report_runtime(&e, &src),
+        }
+    }
+
+    // a `:command`; false when the repl should end
+    fn command(&mut self, line: &str) -> bool {
+        let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        let code = || Source { name: "<repl>".into(), code: rest.to_string(), path: None };
+        // the commands below report their own problems, so their exit codes are not needed
+        match name {
+            ":tokens" => { let _ = tokens(&code()); }
+            ":ast" => { let _ = ast(&code(), false); }
+            ":env" => {
+                for (name, value) in self.env.bindings() {
+                    println!("{} = {}", name, value);
+                }
+            }
+            ":load" if rest.is_empty() => eprintln!("usage: :load <file>"),
+            ":load" => {
+                if let Ok(src) = read_source(rest) {
+                    self.run(src, false);
+                }
+            }
+            ":reset" => *self = Session::new(),
+            ":help" => println!("{}", HELP),
+            ":quit" | ":q" => return false,
+            _ => eprintln!("unknown command `{}`, see :help", name),
+        }
+        true
+    }
+}
+
+pub fn start() -> Result<(), u8> {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("error: cannot start the repl: {}", e);
+            return Err(EXIT_USAGE);
+        }
+    };
+    let history = history_file();
+    if let Some(path) = &history {
+        // there is no history yet on the first run
+        let _ = editor.load_history(path);
+    }
+
+    println!("zeet {}, type :help for the commands", env!("CARGO_PKG_VERSION"));
+    let mut session = Session::new();
+    let mut buffer = String::new();
+    loop {
+        let prompt = if buffer.is_empty() { PROMPT } else { MORE };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if buffer.is_empty() && line.trim_start().starts_with(':') {
+                    let _ = editor.add_history_entry(line.trim());
+                    if !session.command(line.trim()) {
+                        break;
+                    }
+                    continue;
+                }
+                buffer.push_str(&line);
+                buffer.push('\n');
+                if buffer.trim().is_empty() {
+                    buffer.clear();
+                    continue;
+                }
+                // keep reading until every block opened so far has its `__`
+                let (toks, _) = Lexer::new(&buffer).tokenize();
+                if open_blocks(&toks) > 0 {
+                    continue;
+                }
+                let _ = editor.add_history_entry(buffer.trim_end());
+                let src = Source { name: "<repl>".into(), code: std::mem::take(&mut buffer), path: None };
+                session.run(src, true);
+            }
+            // ctrl-c throws away the entry being typed
+            Err(ReadlineError::Interrupted) => buffer.clear(),
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            }
+        }
+    }
+
+    if let Some(path) = &history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("warning: cannot save history to `{}`: {}", path.display(), e);
+        }
+    }
+    Ok(())
+}
+
+fn history_file() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".zeet_history"))
+}
@@ -0,0 +1,22 @@
+use serde::Serialize;
+
+// a region of source text: byte offsets [start, end) plus the 1-based
+// line and column where it begins
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Self {
+        Span { start, end, line, col }
+    }
+
+    // the smallest span covering both `self` and `other` (which must not come before `self`)
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end.max(self.end), ..self }
+    }
+}
@@ -0,0 +1,204 @@
+use crate::builtins::argument_error;
+use crate::environment::{Environment, Value};
+use crate::interpreter::{Interp, RuntimeError, E_ARITY, E_IO};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+// the modules that come with zeet; programs still have to import them
+pub fn install(interp: &mut Interp) {
+    interp.register_module("http_request", install_http);
+    interp.register_module("math", install_math);
+}
+
+// how long to wait for a server to connect, accept the request or send more of its answer
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn install_http(m: &Environment) {
+    // request(url): GET a plain http:// url, giving { status, headers, body };
+    // header names are lowercased
+    m.define_builtin("request", Some(1), |_, args| match &args[0] {
+        Value::Str(url) => get(url),
+        other => Err(argument_error("request", 1, "a url string", other)),
+    });
+}
+
+fn get(url: &str) -> Result<Value, RuntimeError> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => {
+            return Err(RuntimeError::new(E_IO, format!("cannot request `{}`", url))
+                .with_note("only http:// urls are supported, https needs TLS"));
+        }
+        None => return Err(RuntimeError::new(E_IO, format!("`{}` is not an http:// url", url))),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let (host, port) = match authority.rsplit_once(':') {
+        Some((host, port)) => match port.parse::<u16>() {
+            Ok(port) => (host, port),
+            Err(_) => return Err(RuntimeError::new(E_IO, format!("`{}` has an invalid port", url))),
+        },
+        None => (authority, 80),
+    };
+
+    let failed = |e: io::Error| RuntimeError::new(E_IO, format!("request to `{}` failed: {}", url, e));
+    let addr = (host, port).to_socket_addrs().map_err(failed)?.next()
+        .ok_or_else(|| RuntimeError::new(E_IO, format!("cannot find the host of `{}`", url)))?;
+    let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT).map_err(failed)?;
+    stream.set_read_timeout(Some(TIMEOUT)).map_err(failed)?;
+    stream.set_write_timeout(Some(TIMEOUT)).map_err(failed)?;
+    // `Connection: close` lets the end of the stream mark the end of the answer
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: zeet/{}\r\nAccept: */*\r\nConnection: close\r\n\r\n",
+        path, authority, env!("CARGO_PKG_VERSION")
+    );
+    stream.write_all(request.as_bytes()).map_err(failed)?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).map_err(failed)?;
+
+    parse_response(&raw).ok_or_else(|| RuntimeError::new(E_IO, format!("`{}` did not answer with valid HTTP", url)))
+}
+
+fn parse_response(raw: &[u8]) -> Option<Value> {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..split]).ok()?;
+    let mut body = raw[split + 4..].to_vec();
+
+    // HTTP/1.1 200 OK
+    let mut lines = head.split("\r\n");
+    let mut status_line = lines.next()?.splitn(3, ' ');
+    if !status_line.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let status: f64 = status_line.next()?.parse().ok()?;
+    let mut headers = BTreeMap::new();
+    for line in lines {
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), Value::Str(value.trim().to_string()));
+    }
+    if matches!(headers.get("transfer-encoding"), Some(Value::Str(te)) if te.eq_ignore_ascii_case("chunked")) {
+        body = dechunk(&body)?;
+    }
+
+    let mut response = BTreeMap::new();
+    response.insert("status".to_string(), Value::Num(status));
+    response.insert("headers".to_string(), Value::record(headers));
+    response.insert("body".to_string(), Value::Str(String::from_utf8_lossy(&body).into_owned()));
+    Some(Value::record(response))
+}
+
+// a `Transfer-Encoding: chunked` body: hex sizes, each followed by that many bytes,
+// ending with a chunk of size 0
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    loop {
+        let end = data.windows(2).position(|w| w == b"\r\n")?;
+        // anything after `;` is a chunk extension, which we ignore
+        let size = std::str::from_utf8(&data[..end]).ok()?.split(';').next()?.trim();
+        let size = usize::from_str_radix(size, 16).ok()?;
+        data = &data[end + 2..];
+        if size == 0 {
+            return Some(out);
+        }
+        out.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+fn install_math(m: &Environment) {
+    m.define("pi", Value::Num(std::f64::consts::PI));
+    m.define("e", Value::Num(std::f64::consts::E));
+
+    let unary = [
+        ("sqrt", f64::sqrt as fn(f64) -> f64),
+        ("abs", f64::abs),
+        ("floor", f64::floor),
+        ("ceil", f64::ceil),
+        ("round", f64::round),
+        ("sin", f64::sin),
+        ("cos", f64::cos),
+        ("tan", f64::tan),
+        ("log", f64::ln),
+    ];
+    for (name, f) in unary {
+        m.define_builtin(name, Some(1), move |_, args| Ok(Value::Num(f(number(name, &args[0], 1)?))));
+    }
+
+    m.define_builtin("pow", Some(2), |_, args| {
+        Ok(Value::Num(number("pow", &args[0], 1)?.powf(number("pow", &args[1], 2)?)))
+    });
+
+    // min(a, b, ...) / max(a, b, ...): the smallest / largest of at least one number
+    let extremes = [("min", f64::min as fn(f64, f64) -> f64), ("max", f64::max)];
+    for (name, pick) in extremes {
+        m.define_builtin(name, None, move |_, args| {
+            if args.is_empty() {
+                return Err(RuntimeError::new(E_ARITY, format!("`{}` expects at least 1 argument, got 0", name)));
+            }
+            let mut best = number(name, &args[0], 1)?;
+            for (i, arg) in args.iter().enumerate().skip(1) {
+                best = pick(best, number(name, arg, i + 1)?);
+            }
+            Ok(Value::Num(best))
+        });
+    }
+}
+
+fn number(name: &str, v: &Value, position: usize) -> Result<f64, RuntimeError> {
+    match v {
+        Value::Num(n) => Ok(*n),
+        other => Err(argument_error(name, position, "a number", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dechunk, parse_response};
+    use crate::environment::Value;
+
+    fn field(v: &Value, name: &str) -> Value {
+        match v {
+            Value::Record(fields) => fields.borrow()[name].clone(),
+            other => panic!("not a record: {}", other),
+        }
+    }
+
+    #[test]
+    fn chunks_are_joined() {
+        assert_eq!(dechunk(b"5\r\nhello\r\n6;name=x\r\n world\r\n0\r\n\r\n"), Some(b"hello world".to_vec()));
+        assert_eq!(dechunk(b"A\r\n0123456789\r\n0\r\n"), Some(b"0123456789".to_vec()));
+    }
+
+    #[test]
+    fn truncated_chunks_are_rejected() {
+        assert_eq!(dechunk(b"5\r\nhel"), None);
+        assert_eq!(dechunk(b"zz\r\nhello\r\n0\r\n"), None);
+    }
+
+    #[test]
+    fn response_has_status_headers_and_body() {
+        let raw = b"HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nX-Empty:\r\n\r\nno such page";
+        let response = parse_response(raw).unwrap();
+        assert_eq!(field(&response, "status").to_string(), "404");
+        assert_eq!(field(&response, "body").to_string(), "no such page");
+        let headers = field(&response, "headers");
+        assert_eq!(field(&headers, "content-type").to_string(), "text/plain");
+        assert_eq!(field(&headers, "x-empty").to_string(), "");
+    }
+
+    #[test]
+    fn chunked_response_is_decoded() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: Chunked\r\n\r\n3;ext\r\nabc\r\n0\r\n\r\n";
+        assert_eq!(field(&parse_response(raw).unwrap(), "body").to_string(), "abc");
+    }
+
+    #[test]
+    fn other_protocols_are_rejected() {
+        assert!(parse_response(b"SSH-2.0-OpenSSH\r\n\r\n").is_none());
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_none());
+    }
+}
@@ -1,8 +1,12 @@
+use crate::span::Span;
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     // Keywords / Symbols
     Import,
     From,
+    Export,
     Arrow,       // ->
     FnKw,        // __fn
     Equals,      // =
@@ -11,12 +15,35 @@ pub enum Token {
     RAngle,      // >
     LParen,
     RParen,
+    LBracket,    // [
+    RBracket,    // ]
+    LBrace,      // {
+    RBrace,      // }
+    Dot,         // .
     Comma,
+    // symbolic operator aliases
+    Plus,        // +
+    Minus,       // -
+    Star,        // *
+    Slash,       // /
+    LtEq,        // <=
+    GtEq,        // >=
+    EqEq,        // ==
+    NotEq,       // !=
     If,
     Then,        // recognized via "- then," but we normalize
     Otherwise,
     Run,
     Ret,
+    Let,
+    Const,
+    While,
+    Each,
+    In,
+    Repeat,
+    Do,          // "- do," opens a loop body
+    Stop,        // break
+    Skip,        // continue
     Underscore,  // __ (end of function marker)
     Eof,
 
@@ -27,4 +54,67 @@ pub enum Token {
     StringLit(String),
     NumberLit(f64),
     BoolLit(bool),
-}
\ No newline at end of file
+    NullLit,
+}
+
+// a token together with the source text it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+// how a token is named in error messages
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            Token::Import => "`import`",
+            Token::From => "`from`",
+            Token::Export => "`export`",
+            Token::Arrow => "`->`",
+            Token::FnKw => "`__fn`",
+            Token::Equals => "`=`",
+            Token::Colon => "`:`",
+            Token::LAngle => "`<`",
+            Token::RAngle => "`>`",
+            Token::LParen => "`(`",
+            Token::RParen => "`)`",
+            Token::LBracket => "`[`",
+            Token::RBracket => "`]`",
+            Token::LBrace => "`{`",
+            Token::RBrace => "`}`",
+            Token::Dot => "`.`",
+            Token::Comma => "`,`",
+            Token::Plus => "`+`",
+            Token::Minus => "`-`",
+            Token::Star => "`*`",
+            Token::Slash => "`/`",
+            Token::LtEq => "`<=`",
+            Token::GtEq => "`>=`",
+            Token::EqEq => "`==`",
+            Token::NotEq => "`!=`",
+            Token::If => "`if`",
+            Token::Then => "`then`",
+            Token::Otherwise => "`otherwise`",
+            Token::Run => "`run`",
+            Token::Ret => "`ret`",
+            Token::Let => "`let`",
+            Token::Const => "`const`",
+            Token::While => "`while`",
+            Token::Each => "`each`",
+            Token::In => "`in`",
+            Token::Repeat => "`repeat`",
+            Token::Do => "`do`",
+            Token::Stop => "`stop`",
+            Token::Skip => "`skip`",
+            Token::Underscore => "`__`",
+            Token::Eof => "end of file",
+            Token::Identifier(name) => return write!(f, "`{}`", name),
+            Token::StringLit(s) => return write!(f, "string \"{}\"", s),
+            Token::NumberLit(n) => return write!(f, "number {}", n),
+            Token::BoolLit(b) => return write!(f, "`{}`", b),
+            Token::NullLit => "`null`",
+        };
+        f.write_str(text)
+    }
+}